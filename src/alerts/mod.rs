@@ -0,0 +1,404 @@
+use {
+    crate::{
+        config::{AlertRuleConfig, Operator},
+        newrelic::metric::Metric,
+        scheduler,
+        state::{AppState, SharedState},
+    },
+    chrono::Utc,
+    log::{error, info, warn},
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::{Arc, RwLock},
+        time::{Duration, Instant},
+    },
+    tokio::{sync::watch, task::JoinHandle},
+};
+
+/// What a rule evaluates: a metric, or one metric divided by another.
+#[derive(Debug, Clone, Copy)]
+pub struct Expression {
+    numerator: Metric,
+    denominator: Option<Metric>,
+}
+
+impl Expression {
+    /// Parses `metric` or `metric / metric`, by path or snake_case name.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let metric = |name: &str| {
+            Metric::from_name(name.trim())
+                .ok_or_else(|| format!("unknown metric {} in expression", name.trim()))
+        };
+        let mut parts = expression.split('/');
+        let numerator = metric(parts.next().unwrap_or_default())?;
+        let denominator = parts.next().map(metric).transpose()?;
+        if parts.next().is_some() {
+            return Err(String::from(
+                "expression must be a metric or one metric divided by another",
+            ));
+        }
+        Ok(Self {
+            numerator,
+            denominator,
+        })
+    }
+
+    /// None when either side has no data or the denominator is zero.
+    async fn evaluate(
+        &self,
+        state: &AppState,
+        rule: &AlertRuleConfig,
+    ) -> Result<Option<f64>, String> {
+        let fetch = |metric| {
+            state.fetch_metric(
+                metric,
+                &rule.application_name,
+                &rule.since,
+                &rule.until,
+                &rule.k8s,
+                "alert",
+            )
+        };
+        let numerator = match fetch(self.numerator).await?.value {
+            Some(value) => value as f64,
+            None => return Ok(None),
+        };
+        let denominator = match self.denominator {
+            Some(metric) => match fetch(metric).await?.value {
+                Some(value) if value != 0.0 => value as f64,
+                _ => return Ok(None),
+            },
+            None => 1.0,
+        };
+        Ok(Some(numerator / denominator))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Ok,
+    /// Breaching, but not for `for` evaluations yet
+    Pending,
+    Firing,
+}
+
+/// Where a rule stands, as shown by `GET /alerts`.
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleState {
+    pub rule: String,
+    pub application_name: String,
+    pub expression: String,
+    pub operator: Operator,
+    pub threshold: f64,
+    pub resolve_threshold: f64,
+    pub status: AlertStatus,
+    pub value: Option<f64>,
+    /// Consecutive breaching evaluations
+    pub breaches: u32,
+    /// Consecutive recovered evaluations while firing
+    pub recoveries: u32,
+    pub firing_since: Option<String>,
+    pub last_evaluated: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl RuleState {
+    fn new(rule: &AlertRuleConfig) -> Self {
+        Self {
+            rule: rule.name.clone(),
+            application_name: rule.application_name.clone(),
+            expression: rule.expression.clone(),
+            operator: rule.operator,
+            threshold: rule.threshold,
+            resolve_threshold: rule.get_resolve_threshold(),
+            status: AlertStatus::Ok,
+            value: None,
+            breaches: 0,
+            recoveries: 0,
+            firing_since: None,
+            last_evaluated: None,
+            last_error: None,
+        }
+    }
+
+    /// Picks up expression or threshold changes from a reload, keeping the status.
+    fn refresh(&mut self, rule: &AlertRuleConfig) {
+        self.application_name = rule.application_name.clone();
+        self.expression = rule.expression.clone();
+        self.operator = rule.operator;
+        self.threshold = rule.threshold;
+        self.resolve_threshold = rule.get_resolve_threshold();
+    }
+
+    /// Applies one evaluation and returns the event to emit, if the rule fired or resolved.
+    fn observe(&mut self, rule: &AlertRuleConfig, value: f64) -> Option<&'static str> {
+        self.value = Some(value);
+        self.last_error = None;
+        match self.status {
+            AlertStatus::Ok | AlertStatus::Pending => {
+                if !rule.operator.holds(value, rule.threshold) {
+                    self.breaches = 0;
+                    self.status = AlertStatus::Ok;
+                    return None;
+                }
+                self.breaches += 1;
+                if self.breaches < rule.for_evaluations {
+                    self.status = AlertStatus::Pending;
+                    return None;
+                }
+                self.status = AlertStatus::Firing;
+                self.recoveries = 0;
+                self.firing_since = Some(Utc::now().to_rfc3339());
+                Some("firing")
+            }
+            AlertStatus::Firing => {
+                if rule.operator.holds(value, rule.get_resolve_threshold()) {
+                    self.recoveries = 0;
+                    return None;
+                }
+                self.recoveries += 1;
+                if self.recoveries < rule.resolve_after {
+                    return None;
+                }
+                self.status = AlertStatus::Ok;
+                self.breaches = 0;
+                self.recoveries = 0;
+                self.firing_since = None;
+                Some("resolved")
+            }
+        }
+    }
+}
+
+/// Rule states by rule name, kept across config reloads.
+#[derive(Clone, Default)]
+pub struct AlertStore(Arc<RwLock<BTreeMap<String, RuleState>>>);
+
+impl AlertStore {
+    pub fn snapshot(&self) -> Vec<RuleState> {
+        self.0
+            .read()
+            .map(|states| states.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut BTreeMap<String, RuleState>) -> T) -> Option<T> {
+        self.0.write().ok().map(|mut states| f(&mut states))
+    }
+}
+
+/// Evaluates the configured rules whenever they are due and emits firing and
+/// resolved events to the log and the rule's webhooks. A rule still evaluating
+/// skips its turn, so `RuleState::observe` sees results in order. Returns once
+/// `stop` changes and the evaluations in flight have finished.
+pub async fn run(state: SharedState, store: AlertStore, mut stop: watch::Receiver<bool>) {
+    let mut last_run: HashMap<String, Instant> = HashMap::new();
+    let mut in_flight: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = stop.changed() => {
+                info!("Stopping alert rules, waiting for {} running evaluations", in_flight.len());
+                for (_, evaluation) in in_flight {
+                    let _ = evaluation.await;
+                }
+                return;
+            }
+            _ = ticker.tick() => {}
+        }
+        in_flight.retain(|_, evaluation| !evaluation.is_finished());
+        let current = state.load();
+        let rules = &current.config.alerts.rules;
+        last_run.retain(|name, _| rules.iter().any(|r| &r.name == name));
+        store.update(|states| states.retain(|name, _| rules.iter().any(|r| &r.name == name)));
+        for rule in rules {
+            let interval = Duration::from_secs(rule.interval);
+            if matches!(last_run.get(&rule.name), Some(at) if at.elapsed() < interval) {
+                continue;
+            }
+            last_run.insert(rule.name.clone(), Instant::now());
+            if in_flight.contains_key(&rule.name) {
+                warn!(
+                    "Alert rule {} is still evaluating, skipping this evaluation",
+                    rule.name
+                );
+                continue;
+            }
+            let state = state.clone();
+            let store = store.clone();
+            let name = rule.name.clone();
+            let evaluation =
+                actix_web::rt::spawn(async move { evaluate(&state, &store, &name).await });
+            in_flight.insert(rule.name.clone(), evaluation);
+        }
+    }
+}
+
+async fn evaluate(state: &SharedState, store: &AlertStore, name: &str) {
+    let current = state.load();
+    let rule = match current.config.alerts.rules.iter().find(|r| r.name == name) {
+        Some(rule) => rule,
+        None => return,
+    };
+    let result = match Expression::parse(&rule.expression) {
+        Ok(expression) => expression.evaluate(&current, rule).await,
+        Err(e) => Err(e),
+    };
+    let now = Utc::now().to_rfc3339();
+    let event = store.update(|states| {
+        let entry = states
+            .entry(rule.name.clone())
+            .or_insert_with(|| RuleState::new(rule));
+        entry.refresh(rule);
+        entry.last_evaluated = Some(now);
+        match result {
+            Ok(Some(value)) => entry
+                .observe(rule, value)
+                .map(|event| (event, entry.clone())),
+            Ok(None) => {
+                warn!("Alert rule {} got no data, keeping its state", rule.name);
+                None
+            }
+            Err(e) => {
+                error!("Alert rule {} could not be evaluated: {}", rule.name, e);
+                entry.last_error = Some(e);
+                None
+            }
+        }
+    });
+    if let Some(Some((event, rule_state))) = event {
+        emit(&current, rule, event, &rule_state).await;
+    }
+}
+
+async fn emit(current: &AppState, rule: &AlertRuleConfig, event: &str, rule_state: &RuleState) {
+    let payload = serde_json::json!({
+        "event": event,
+        "rule": rule_state.rule,
+        "application_name": rule_state.application_name,
+        "expression": rule_state.expression,
+        "operator": rule_state.operator,
+        "threshold": rule_state.threshold,
+        "resolve_threshold": rule_state.resolve_threshold,
+        "value": rule_state.value,
+        "timestamp": Utc::now().to_rfc3339(),
+    });
+    warn!("Alert {} {}: {}", rule.name, event, payload);
+    let body = payload.to_string().into_bytes();
+    let name = format!("alert:{}", rule.name);
+    for url in &rule.webhooks {
+        scheduler::deliver(
+            &current.webhook_client,
+            &current.config.scheduler,
+            &name,
+            url,
+            &body,
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::newrelic::filter::K8sFilter};
+
+    fn rule(for_evaluations: u32, resolve_after: u32) -> AlertRuleConfig {
+        AlertRuleConfig {
+            name: String::from("cpu"),
+            application_name: String::from("checkout"),
+            expression: String::from("cpu_used_core / cpu_requested_core"),
+            operator: Operator::Above,
+            threshold: 0.9,
+            resolve_threshold: Some(0.7),
+            for_evaluations,
+            resolve_after,
+            interval: 60,
+            since: String::from("5 minutes ago"),
+            until: String::from("now"),
+            k8s: K8sFilter::default(),
+            webhooks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn fires_after_consecutive_breaches() {
+        let rule = rule(3, 1);
+        let mut state = RuleState::new(&rule);
+        assert_eq!(state.observe(&rule, 0.95), None);
+        assert_eq!(state.status, AlertStatus::Pending);
+        assert_eq!(state.observe(&rule, 0.95), None);
+        assert_eq!(state.observe(&rule, 0.95), Some("firing"));
+        assert_eq!(state.status, AlertStatus::Firing);
+        assert!(state.firing_since.is_some());
+        // no repeated event while it keeps firing
+        assert_eq!(state.observe(&rule, 0.99), None);
+    }
+
+    #[test]
+    fn a_recovery_resets_pending_breaches() {
+        let rule = rule(2, 1);
+        let mut state = RuleState::new(&rule);
+        state.observe(&rule, 0.95);
+        assert_eq!(state.observe(&rule, 0.5), None);
+        assert_eq!(state.status, AlertStatus::Ok);
+        assert_eq!(state.breaches, 0);
+        assert_eq!(state.observe(&rule, 0.95), None);
+        assert_eq!(state.observe(&rule, 0.95), Some("firing"));
+    }
+
+    #[test]
+    fn stays_firing_between_the_thresholds() {
+        let rule = rule(1, 1);
+        let mut state = RuleState::new(&rule);
+        assert_eq!(state.observe(&rule, 0.95), Some("firing"));
+        // below threshold but above resolve_threshold
+        assert_eq!(state.observe(&rule, 0.8), None);
+        assert_eq!(state.status, AlertStatus::Firing);
+        assert_eq!(state.recoveries, 0);
+        assert_eq!(state.observe(&rule, 0.6), Some("resolved"));
+        assert_eq!(state.status, AlertStatus::Ok);
+        assert!(state.firing_since.is_none());
+    }
+
+    #[test]
+    fn resolves_after_consecutive_recoveries() {
+        let rule = rule(1, 2);
+        let mut state = RuleState::new(&rule);
+        state.observe(&rule, 0.95);
+        assert_eq!(state.observe(&rule, 0.6), None);
+        // going back up resets the recoveries
+        assert_eq!(state.observe(&rule, 0.8), None);
+        assert_eq!(state.recoveries, 0);
+        assert_eq!(state.observe(&rule, 0.6), None);
+        assert_eq!(state.observe(&rule, 0.6), Some("resolved"));
+        assert_eq!((state.breaches, state.recoveries), (0, 0));
+    }
+
+    #[test]
+    fn below_operator_fires_on_low_values() {
+        let mut rule = rule(1, 1);
+        rule.operator = Operator::Below;
+        rule.threshold = 10.0;
+        rule.resolve_threshold = Some(20.0);
+        let mut state = RuleState::new(&rule);
+        assert_eq!(state.observe(&rule, 12.0), None);
+        assert_eq!(state.observe(&rule, 5.0), Some("firing"));
+        assert_eq!(state.observe(&rule, 15.0), None);
+        assert_eq!(state.observe(&rule, 25.0), Some("resolved"));
+    }
+
+    #[test]
+    fn expressions_parse_one_or_two_metrics() {
+        assert!(Expression::parse("cpu_used_core").is_ok());
+        let ratio = Expression::parse("cpu_used_core / cpu-requested-core").unwrap();
+        assert!(ratio.denominator.is_some());
+        assert!(Expression::parse("cpu_used_core / nope").is_err());
+        let err = Expression::parse("cpu_used_core / cpu_used_core / cpu_used_core").unwrap_err();
+        assert_eq!(
+            err,
+            "expression must be a metric or one metric divided by another"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use {
+    crate::{
+        cli::{OutputFormat, QueryOptions},
+        config::{Config, IdentityConfig},
+        handler::v1::model::Comparison,
+        newrelic::{
+            filter::{K8sFilter, Target},
+            metric::{is_valid_percentile, Metric},
+            model::NewrelicQueryResult,
+            newrelic::Newrelic,
+            window::{self, TimeWindow},
+        },
+    },
+    serde_json::{json, Value},
+    std::error::Error,
+};
+
+type CommandResult = Result<(), Box<dyn Error>>;
+
+pub fn check_config(config_path: &str) -> CommandResult {
+    Config::new(config_path)?;
+    println!("config {} is valid", config_path);
+    Ok(())
+}
+
+pub async fn query(config_path: &str, options: &QueryOptions) -> CommandResult {
+    let metric = options.get_metric();
+    let filter = options.get_filter();
+    filter.validate()?;
+    match metric {
+        Metric::ResponseTimePercentile(p) if !is_valid_percentile(p) => {
+            return Err("percentile must be between 0 and 100 exclusive".into());
+        }
+        Metric::Apdex(t) if t <= 0.0 => return Err("apdex-t must be positive".into()),
+        _ => {}
+    }
+    let config = Config::new(config_path)?;
+    let newrelic = Newrelic::new(&config.newrelic);
+    let window = TimeWindow::parse(&options.since, &options.until, &config.time)?;
+    let target = config.resolve_target(&metric, &options.application_name, &filter);
+    let mut nrql = metric.get_query(&target, &window.since, &window.until);
+    if let Some(compare_with) = &options.compare_with {
+        nrql.push_str(&window::compare_with(compare_with)?);
+    }
+    let raw = newrelic.run_nrql(&nrql, metric.name()).await?;
+    let mut rows = vec![
+        ("metric", json!(metric.slug())),
+        ("application_name", json!(options.application_name)),
+        ("nrql", json!(nrql)),
+    ];
+    match serde_json::from_value(raw.clone()) {
+        Ok(NewrelicQueryResult::Ok(res)) => rows.push(("value", json!(metric.extract(&res)))),
+        Ok(NewrelicQueryResult::Compare(res)) => {
+            let current = metric.extract(res.get_current());
+            let previous = metric.extract(res.get_previous());
+            rows.push(("value", json!(current)));
+            rows.push(("previous", json!(previous)));
+            if let Some(current) = current {
+                rows.push((
+                    "delta_percent",
+                    json!(Comparison::new(current, previous).delta_percent),
+                ));
+            }
+        }
+        Ok(NewrelicQueryResult::Err(e)) => {
+            return Err(format!("New Relic rejected the query: {}", e.get_error_msg()).into());
+        }
+        _ => rows.push(("value", Value::Null)),
+    }
+    rows.push(("raw", raw));
+    print_rows(options.output, &rows);
+    Ok(())
+}
+
+pub async fn nrql(config_path: &str, nrql: &str, output: OutputFormat) -> CommandResult {
+    let newrelic = Newrelic::new(&Config::new(config_path)?.newrelic);
+    let raw = newrelic.run_nrql(nrql, "nrql").await?;
+    print_rows(output, &[("nrql", json!(nrql)), ("raw", raw)]);
+    Ok(())
+}
+
+pub fn list_metrics(output: OutputFormat) -> CommandResult {
+    let identity = IdentityConfig::default();
+    let metrics: Vec<Value> = Metric::ALL
+        .iter()
+        .map(|m| {
+            json!({
+                "metric": m.slug(),
+                "nrql": m.get_query(
+                    &Target {
+                        attribute: identity.get_attribute(m),
+                        application_name: String::from("{application_name}"),
+                        k8s: K8sFilter::default(),
+                    },
+                    "{start_time}",
+                    "{end_time}",
+                ),
+            })
+        })
+        .collect();
+    match output {
+        OutputFormat::Json => println!("{}", Value::Array(metrics)),
+        OutputFormat::Table => {
+            for m in metrics {
+                println!(
+                    "{:<28}{}",
+                    m["metric"].as_str().unwrap_or_default(),
+                    m["nrql"].as_str().unwrap_or_default()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints `rows` as one JSON object, or as an aligned key/value table with
+/// nested JSON pretty-printed.
+fn print_rows(output: OutputFormat, rows: &[(&str, Value)]) {
+    match output {
+        OutputFormat::Json => {
+            let object: serde_json::Map<String, Value> = rows
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect();
+            println!("{}", Value::Object(object));
+        }
+        OutputFormat::Table => {
+            for (key, value) in rows {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    Value::Object(_) | Value::Array(_) => {
+                        serde_json::to_string_pretty(value).unwrap_or_default()
+                    }
+                    other => other.to_string(),
+                };
+                println!("{:<18}{}", key, value);
+            }
+        }
+    }
+}
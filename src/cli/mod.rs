@@ -1,14 +1,157 @@
-use structopt::StructOpt;
+pub mod command;
+
+use {
+    crate::{
+        log::LogFormat,
+        newrelic::{filter::K8sFilter, metric::Metric},
+    },
+    log::LevelFilter,
+    std::str::FromStr,
+    structopt::StructOpt,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "Enma, uncontrolled kalimdor replacement")]
 pub struct Options {
     /// Enma config path
-    #[structopt(long = "config.enma", default_value = "configuration/enma.yaml")]
+    #[structopt(
+        long = "config.enma",
+        default_value = "configuration/enma.yaml",
+        global = true
+    )]
     config: String,
     /// log4rs config path
-    #[structopt(long = "config.log", default_value = "configuration/log4rs.yaml")]
+    #[structopt(
+        long = "config.log",
+        default_value = "configuration/log4rs.yaml",
+        global = true
+    )]
     log_config: String,
+    /// Use the built-in logging config with this format (text or json) instead of the log4rs file
+    #[structopt(long = "log.format", global = true)]
+    log_format: Option<LogFormat>,
+    /// Override the root log level (error, warn, info, debug, trace)
+    #[structopt(long = "log.level", global = true)]
+    log_level: Option<LevelFilter>,
+    /// Don't print the startup banner
+    #[structopt(long = "no-banner", global = true)]
+    no_banner: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Run the HTTP server (the default when no subcommand is given)
+    Serve,
+    /// Validate the enma config and exit
+    CheckConfig,
+    /// Run one metric against New Relic and print the value, the NRQL and the raw payload
+    Query(QueryOptions),
+    /// Run a raw NRQL query and print New Relic's payload
+    Nrql {
+        query: String,
+        /// Output format: table or json
+        #[structopt(long, default_value = "table")]
+        output: OutputFormat,
+    },
+    /// List the available metrics and their NRQL
+    ListMetrics {
+        /// Output format: table or json
+        #[structopt(long, default_value = "table")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct QueryOptions {
+    /// Metric to run, see list-metrics
+    pub metric: Metric,
+    /// Application name
+    #[structopt(long = "app")]
+    pub application_name: String,
+    /// Start of the window: relative time, RFC 3339, epoch milliseconds or a local date/time
+    #[structopt(long, default_value = "5 minutes ago")]
+    pub since: String,
+    /// End of the window, same formats as --since
+    #[structopt(long, default_value = "now")]
+    pub until: String,
+    /// Percentile for response-time-percentile, between 0 and 100 exclusive
+    #[structopt(long)]
+    pub percentile: Option<f32>,
+    /// Apdex T threshold in seconds for apdex
+    #[structopt(long = "apdex-t")]
+    pub apdex_t: Option<f32>,
+    /// Kubernetes namespace to scope the Kubernetes metrics to
+    #[structopt(long)]
+    pub namespace: Option<String>,
+    /// Kubernetes cluster to scope the Kubernetes metrics to
+    #[structopt(long = "cluster")]
+    pub cluster_name: Option<String>,
+    /// Pod label the Kubernetes metrics must match, as key=value; repeatable
+    #[structopt(long = "label", parse(try_from_str = parse_label))]
+    pub labels: Vec<(String, String)>,
+    /// Relative time to compare the window with, e.g. "1 week ago"
+    #[structopt(long = "compare-with")]
+    pub compare_with: Option<String>,
+    /// Output format: table or json
+    #[structopt(long, default_value = "table")]
+    pub output: OutputFormat,
+}
+
+impl QueryOptions {
+    /// The metric with --percentile and --apdex-t applied.
+    pub fn get_metric(&self) -> Metric {
+        match self.metric {
+            Metric::ResponseTimePercentile(p) => {
+                Metric::ResponseTimePercentile(self.percentile.unwrap_or(p))
+            }
+            Metric::Apdex(t) => Metric::Apdex(self.apdex_t.unwrap_or(t)),
+            m => m,
+        }
+    }
+
+    pub fn get_filter(&self) -> K8sFilter {
+        K8sFilter {
+            namespace: self.namespace.clone(),
+            cluster_name: self.cluster_name.clone(),
+            labels: self.labels.iter().cloned().collect(),
+        }
+    }
+}
+
+fn parse_label(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("invalid label {}, expected key=value", s)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Self::Table),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown output format {}, expected table or json",
+                s
+            )),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Options {
@@ -23,4 +166,20 @@ impl Options {
     pub fn get_log_config_path(&self) -> &str {
         self.log_config.as_str()
     }
+
+    pub fn get_log_format(&self) -> Option<LogFormat> {
+        self.log_format
+    }
+
+    pub fn get_log_level(&self) -> Option<LevelFilter> {
+        self.log_level
+    }
+
+    pub fn show_banner(&self) -> bool {
+        !self.no_banner
+    }
+
+    pub fn get_command(&self) -> &Command {
+        self.command.as_ref().unwrap_or(&Command::Serve)
+    }
 }
@@ -1,35 +1,987 @@
-use serde::Deserialize;
+use {
+    crate::alerts::Expression,
+    crate::newrelic::{
+        filter::{K8sFilter, Target},
+        metric::Metric,
+        nrql,
+        window::TimeWindow,
+    },
+    chrono_tz::Tz,
+    serde::{Deserialize, Serialize},
+    std::{collections::HashMap, fmt},
+};
 
 #[derive(Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub newrelic: NewrelicConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub nrql: NrqlConfig,
+    #[serde(default)]
+    pub time: TimeConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub slos: Vec<SloConfig>,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub stale_while_error: StaleConfig,
+    #[serde(default)]
+    pub identity: IdentityConfig,
+    /// Logical service names callers may pass as application_name
+    #[serde(default)]
+    pub applications: HashMap<String, ApplicationConfig>,
 }
 
 #[derive(Deserialize)]
 pub struct ServerConfig {
-    pub port: i32,
+    pub port: u16,
     pub host: String,
+    /// Seconds to keep serving after SIGTERM with readiness failing, so load balancers can drain
+    #[serde(default = "default_shutdown_delay")]
+    pub shutdown_delay: u64,
+    /// Seconds in-flight requests get to finish once the server stops accepting
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+}
+
+fn default_shutdown_delay() -> u64 {
+    5
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+#[derive(Deserialize)]
+pub struct ReloadConfig {
+    /// Seconds between checks of the config file mtime, 0 only reloads on SIGHUP
+    pub refresh_rate: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self { refresh_rate: 10 }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP traces endpoint, e.g. http://localhost:4318/v1/traces. Tracing is off when unset
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Fraction of root traces to sample, between 0 and 1
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: String::from("enma"),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+/// Guard for the raw NRQL passthrough endpoint, which is off by default.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct NrqlConfig {
+    pub enabled: bool,
+    /// Event types any query may read from, e.g. Transaction or K8sContainerSample
+    pub allowed_event_types: Vec<String>,
+    /// Furthest back a query's SINCE may reach, in minutes
+    pub max_since_minutes: u64,
+    pub clients: Vec<NrqlClientConfig>,
+}
+
+impl Default for NrqlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed_event_types: Vec::new(),
+            max_since_minutes: 24 * 60,
+            clients: Vec::new(),
+        }
+    }
+}
+
+/// A caller of the NRQL endpoint, authenticated with `Authorization: Bearer <token>`.
+#[derive(Deserialize)]
+pub struct NrqlClientConfig {
+    pub name: String,
+    pub token: String,
+    /// Event types this client may query, `*` for every allowed event type
+    pub scopes: Vec<String>,
+}
+
+/// How start_time and end_time are read and which windows are allowed.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TimeConfig {
+    /// IANA timezone for dates sent without an offset, defaults to $TZ or UTC
+    pub timezone: String,
+    /// Longest window a metric query may cover, in minutes
+    pub max_window_minutes: u64,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        Self {
+            timezone: std::env::var("TZ")
+                .ok()
+                .filter(|tz| tz.parse::<Tz>().is_ok())
+                .unwrap_or_else(|| String::from("UTC")),
+            max_window_minutes: 7 * 24 * 60,
+        }
+    }
+}
+
+impl TimeConfig {
+    /// The configured timezone, UTC if it doesn't parse (rejected by validate).
+    pub fn get_timezone(&self) -> Tz {
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+}
+
+/// Metric queries enma runs on its own and pushes to webhooks.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub jobs: Vec<JobConfig>,
+    /// Key for the `X-Enma-Signature` HMAC-SHA256 of each payload
+    pub secret: String,
+    /// Delivery attempts per webhook before the payload goes to the dead-letter log
+    pub max_attempts: u32,
+    /// Wait before the first retry in milliseconds, doubled on every further retry
+    pub retry_backoff_ms: u64,
+    /// Seconds a webhook gets to respond
+    pub timeout: u64,
+    /// File failed deliveries are appended to as JSON lines, besides the error log
+    pub dead_letter_path: Option<String>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            jobs: Vec::new(),
+            secret: String::new(),
+            max_attempts: 3,
+            retry_backoff_ms: 500,
+            timeout: 10,
+            dead_letter_path: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobConfig {
+    pub name: String,
+    /// Metric path, e.g. throughput or response-time-p95
+    pub metric: String,
+    pub application_name: String,
+    /// Seconds between runs
+    pub interval: u64,
+    #[serde(default = "default_job_since")]
+    pub since: String,
+    #[serde(default = "default_job_until")]
+    pub until: String,
+    #[serde(flatten)]
+    pub k8s: K8sFilter,
+    pub webhooks: Vec<String>,
+}
+
+fn default_job_since() -> String {
+    String::from("5 minutes ago")
+}
+
+fn default_job_until() -> String {
+    String::from("now")
+}
+
+impl JobConfig {
+    pub fn get_metric(&self) -> Option<Metric> {
+        Metric::from_slug(&self.metric)
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AlertsConfig {
+    pub rules: Vec<AlertRuleConfig>,
+}
+
+/// A threshold on a metric, or on the ratio of two metrics, for one application.
+#[derive(Deserialize)]
+pub struct AlertRuleConfig {
+    pub name: String,
+    pub application_name: String,
+    /// A metric, or two divided, e.g. `cpu_used_core / cpu_requested_core`
+    pub expression: String,
+    pub operator: Operator,
+    pub threshold: f64,
+    /// Value the expression has to get back past before a firing rule resolves,
+    /// defaults to `threshold`
+    #[serde(default)]
+    pub resolve_threshold: Option<f64>,
+    /// Consecutive breaching evaluations before the rule fires
+    #[serde(rename = "for", default = "default_one")]
+    pub for_evaluations: u32,
+    /// Consecutive recovered evaluations before a firing rule resolves
+    #[serde(default = "default_one")]
+    pub resolve_after: u32,
+    /// Seconds between evaluations
+    #[serde(default = "default_alert_interval")]
+    pub interval: u64,
+    #[serde(default = "default_job_since")]
+    pub since: String,
+    #[serde(default = "default_job_until")]
+    pub until: String,
+    #[serde(flatten)]
+    pub k8s: K8sFilter,
+    /// Where firing and resolved events are POSTed, they are always logged
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+fn default_one() -> u32 {
+    1
+}
+
+fn default_alert_interval() -> u64 {
+    60
+}
+
+impl AlertRuleConfig {
+    pub fn get_resolve_threshold(&self) -> f64 {
+        self.resolve_threshold.unwrap_or(self.threshold)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    #[serde(rename = ">")]
+    Above,
+    #[serde(rename = ">=")]
+    AtLeast,
+    #[serde(rename = "<")]
+    Below,
+    #[serde(rename = "<=")]
+    AtMost,
+}
+
+impl Operator {
+    pub fn holds(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            Self::Above => value > threshold,
+            Self::AtLeast => value >= threshold,
+            Self::Below => value < threshold,
+            Self::AtMost => value <= threshold,
+        }
+    }
+}
+
+/// A service level objective over the ratio of good to total events.
+#[derive(Deserialize)]
+pub struct SloConfig {
+    pub name: String,
+    /// NRQL counting good events, without SINCE or UNTIL
+    pub good: String,
+    /// NRQL counting all events, without SINCE or UNTIL
+    pub total: String,
+    /// Target percentage of good events, e.g. 99.9
+    pub objective: f64,
+    /// Days the objective and its error budget cover
+    #[serde(default = "default_slo_window_days")]
+    pub window_days: u64,
+    /// Burn rate both the 1h and 6h windows must exceed to page
+    #[serde(default = "default_fast_burn")]
+    pub fast_burn_threshold: f64,
+    /// Burn rate both the 6h and 3d windows must exceed to open a ticket
+    #[serde(default = "default_slow_burn")]
+    pub slow_burn_threshold: f64,
+    /// Seconds the counts of each window are reused before New Relic is asked again
+    #[serde(default = "default_slo_cache_seconds")]
+    pub cache_seconds: u64,
+}
+
+fn default_slo_cache_seconds() -> u64 {
+    60
+}
+
+fn default_slo_window_days() -> u64 {
+    30
+}
+
+fn default_fast_burn() -> f64 {
+    14.4
+}
+
+/// The SRE workbook's ticket threshold: the 3d window spending budget faster than sustainable
+fn default_slow_burn() -> f64 {
+    1.0
+}
+
+impl SloConfig {
+    /// Fraction of events allowed to be bad, e.g. 0.001 for 99.9
+    pub fn get_error_budget(&self) -> f64 {
+        1.0 - self.objective / 100.0
+    }
+}
+
+/// Local record of fetched metric values, served at `GET /history`.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// JSON lines file values are appended to, history is off when unset
+    pub path: Option<String>,
+    /// Most records one `GET /history` may return
+    pub max_results: usize,
+    /// Size in MiB at which the file is moved to `<path>.1`
+    pub max_file_mb: u64,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_results: 1000,
+            max_file_mb: 64,
+        }
+    }
+}
+
+/// Serving the last good value when New Relic can't be reached, off by default.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct StaleConfig {
+    pub enabled: bool,
+    /// Oldest value in seconds that may still be served, after that the error is returned
+    pub max_staleness: u64,
+}
+
+impl Default for StaleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_staleness: 300,
+        }
+    }
+}
+
+/// Which attribute identifies the application in each metric's NRQL.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    /// Attribute the APM and JVM metrics filter on
+    pub apm_attribute: String,
+    /// Pod label the Kubernetes metrics filter on, e.g. app.kubernetes.io/name
+    pub k8s_label: String,
+    /// Full attribute name per metric, keyed by metric path, e.g. pods-total
+    pub metrics: HashMap<String, String>,
+}
+
+impl Default for IdentityConfig {
+    fn default() -> Self {
+        Self {
+            apm_attribute: String::from("appName"),
+            k8s_label: String::from("app"),
+            metrics: HashMap::new(),
+        }
+    }
+}
+
+impl IdentityConfig {
+    pub fn get_attribute(&self, metric: &Metric) -> String {
+        if let Some(attribute) = self.metrics.get(metric.slug()) {
+            return attribute.clone();
+        }
+        match metric.k8s_attributes() {
+            Some(attributes) => format!("{}{}", attributes.label_prefix, self.k8s_label),
+            None => self.apm_attribute.clone(),
+        }
+    }
+}
+
+/// How one logical service is named in APM and Kubernetes. Unset names fall
+/// back to the logical name.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct ApplicationConfig {
+    pub apm_name: Option<String>,
+    pub k8s_name: Option<String>,
+    pub namespace: Option<String>,
+    pub cluster_name: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct NewrelicConfig {
     api_key: String,
-    account_id: i32,
+    account_id: u32,
+    /// Insights query API base URL, e.g. https://insights-api.eu.newrelic.com for EU accounts
+    #[serde(default = "default_newrelic_endpoint")]
+    endpoint: String,
+}
+
+fn default_newrelic_endpoint() -> String {
+    String::from("https://insights-api.newrelic.com")
 }
 
 impl NewrelicConfig {
     pub fn get_api_key(&self) -> &str {
-        return self.api_key.as_str();
+        self.api_key.as_str()
+    }
+    pub fn get_account_id(&self) -> u32 {
+        self.account_id
+    }
+    pub fn get_endpoint(&self) -> &str {
+        self.endpoint.trim_end_matches('/')
     }
-    pub fn get_account_id(&self) -> i32 {
-        return self.account_id;
+}
+
+/// A single semantic problem found in the config, keyed by its YAML path.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
     }
 }
 
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, std::io::Error),
+    Parse(String, serde_yaml::Error),
+    Invalid(String, Vec<ValidationError>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "could not read config {}: {}", path, e),
+            Self::Parse(path, e) => write!(f, "could not parse config {}: {}", path, e),
+            Self::Invalid(path, errors) => {
+                write!(f, "invalid config {}:", path)?;
+                for e in errors {
+                    write!(f, "\n  - {}", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
-    pub fn new(path: &str) -> Self {
-        let f = std::fs::File::open(path).expect("Config file not found");
-        serde_yaml::from_reader(f).expect("Could not parse the config")
+    /// Resolves what `metric` should filter on for `application_name`, going
+    /// through `applications` when it's a logical name. Namespace and cluster
+    /// from the request win over the mapping.
+    pub fn resolve_target(
+        &self,
+        metric: &Metric,
+        application_name: &str,
+        k8s: &K8sFilter,
+    ) -> Target {
+        let mut k8s = k8s.clone();
+        let application_name = match self.applications.get(application_name) {
+            Some(app) => {
+                if k8s.namespace.is_none() {
+                    k8s.namespace = app.namespace.clone();
+                }
+                if k8s.cluster_name.is_none() {
+                    k8s.cluster_name = app.cluster_name.clone();
+                }
+                let mapped = match metric.k8s_attributes() {
+                    Some(_) => app.k8s_name.as_deref(),
+                    None => app.apm_name.as_deref(),
+                };
+                mapped.unwrap_or(application_name).to_string()
+            }
+            None => application_name.to_string(),
+        };
+        Target {
+            attribute: self.identity.get_attribute(metric),
+            application_name,
+            k8s,
+        }
+    }
+
+    pub fn new(path: &str) -> Result<Self, ConfigError> {
+        let f = std::fs::File::open(path).map_err(|e| ConfigError::Io(path.to_string(), e))?;
+        let config: Config =
+            serde_yaml::from_reader(f).map_err(|e| ConfigError::Parse(path.to_string(), e))?;
+        let errors = config.validate();
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(path.to_string(), errors));
+        }
+        Ok(config)
+    }
+
+    /// Checks everything serde can't express in the types and returns every problem found.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        let mut check = |ok: bool, path: &str, message: &str| {
+            if !ok {
+                errors.push(ValidationError {
+                    path: path.to_string(),
+                    message: message.to_string(),
+                });
+            }
+        };
+        check(
+            !self.server.host.trim().is_empty(),
+            "server.host",
+            "must not be empty",
+        );
+        check(
+            self.server.port != 0,
+            "server.port",
+            "must be between 1 and 65535",
+        );
+        check(
+            !self.newrelic.api_key.trim().is_empty(),
+            "newrelic.api_key",
+            "must not be empty",
+        );
+        check(
+            !self.newrelic.api_key.starts_with('<'),
+            "newrelic.api_key",
+            "still contains the example placeholder",
+        );
+        check(
+            self.newrelic.account_id > 0,
+            "newrelic.account_id",
+            "must be a positive integer",
+        );
+        check(
+            self.newrelic.endpoint.starts_with("http://")
+                || self.newrelic.endpoint.starts_with("https://"),
+            "newrelic.endpoint",
+            "must be an http:// or https:// URL",
+        );
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            check(
+                endpoint.starts_with("http://") || endpoint.starts_with("https://"),
+                "telemetry.otlp_endpoint",
+                "must be an http:// or https:// URL",
+            );
+        }
+        check(
+            (0.0..=1.0).contains(&self.telemetry.sample_ratio),
+            "telemetry.sample_ratio",
+            "must be between 0 and 1",
+        );
+        if self.nrql.enabled {
+            check(
+                !self.nrql.allowed_event_types.is_empty(),
+                "nrql.allowed_event_types",
+                "must list at least one event type when nrql is enabled",
+            );
+            check(
+                !self.nrql.clients.is_empty(),
+                "nrql.clients",
+                "must list at least one client when nrql is enabled",
+            );
+            check(
+                self.nrql.max_since_minutes > 0,
+                "nrql.max_since_minutes",
+                "must be positive",
+            );
+        }
+        check(
+            self.time.timezone.parse::<Tz>().is_ok(),
+            "time.timezone",
+            "must be an IANA timezone like Asia/Jakarta",
+        );
+        check(
+            self.time.max_window_minutes > 0,
+            "time.max_window_minutes",
+            "must be positive",
+        );
+        check(
+            !self.identity.apm_attribute.trim().is_empty(),
+            "identity.apm_attribute",
+            "must not be empty",
+        );
+        check(
+            !self.identity.k8s_label.trim().is_empty() && !self.identity.k8s_label.contains('`'),
+            "identity.k8s_label",
+            "must be a label name without backticks",
+        );
+        for (slug, attribute) in &self.identity.metrics {
+            let path = format!("identity.metrics.{}", slug);
+            check(
+                Metric::from_slug(slug).is_some(),
+                &path,
+                "is not a known metric",
+            );
+            check(
+                !attribute.trim().is_empty() && !attribute.contains('`'),
+                &path,
+                "must be an attribute name without backticks",
+            );
+        }
+        if !self.scheduler.jobs.is_empty() {
+            check(
+                !self.scheduler.secret.trim().is_empty(),
+                "scheduler.secret",
+                "must be set when scheduler jobs are configured",
+            );
+        }
+        check(
+            self.scheduler.max_attempts > 0,
+            "scheduler.max_attempts",
+            "must be positive",
+        );
+        for (i, job) in self.scheduler.jobs.iter().enumerate() {
+            let path = |field: &str| format!("scheduler.jobs[{}].{}", i, field);
+            check(
+                !job.name.trim().is_empty(),
+                &path("name"),
+                "must not be empty",
+            );
+            check(
+                self.scheduler
+                    .jobs
+                    .iter()
+                    .filter(|j| j.name == job.name)
+                    .count()
+                    == 1,
+                &path("name"),
+                "must be unique",
+            );
+            check(
+                job.get_metric().is_some(),
+                &path("metric"),
+                "is not a known metric",
+            );
+            check(job.interval > 0, &path("interval"), "must be positive");
+            check(
+                TimeWindow::parse(&job.since, &job.until, &self.time).is_ok(),
+                &path("since"),
+                "since and until must form a valid window",
+            );
+            check(
+                job.k8s.validate().is_ok(),
+                &path("labels"),
+                "contains an invalid label name",
+            );
+            check(
+                !job.webhooks.is_empty(),
+                &path("webhooks"),
+                "must not be empty",
+            );
+            for (j, url) in job.webhooks.iter().enumerate() {
+                check(
+                    url.starts_with("http://") || url.starts_with("https://"),
+                    &format!("scheduler.jobs[{}].webhooks[{}]", i, j),
+                    "must be an http:// or https:// URL",
+                );
+            }
+        }
+        let needs_secret = self.alerts.rules.iter().any(|r| !r.webhooks.is_empty());
+        if needs_secret {
+            check(
+                !self.scheduler.secret.trim().is_empty(),
+                "scheduler.secret",
+                "must be set when alert rules have webhooks",
+            );
+        }
+        for (i, rule) in self.alerts.rules.iter().enumerate() {
+            let path = |field: &str| format!("alerts.rules[{}].{}", i, field);
+            check(
+                !rule.name.trim().is_empty(),
+                &path("name"),
+                "must not be empty",
+            );
+            check(
+                self.alerts
+                    .rules
+                    .iter()
+                    .filter(|r| r.name == rule.name)
+                    .count()
+                    == 1,
+                &path("name"),
+                "must be unique",
+            );
+            if let Err(e) = Expression::parse(&rule.expression) {
+                check(false, &path("expression"), &e);
+            }
+            let resolve = rule.get_resolve_threshold();
+            check(
+                match rule.operator {
+                    Operator::Above | Operator::AtLeast => resolve <= rule.threshold,
+                    Operator::Below | Operator::AtMost => resolve >= rule.threshold,
+                },
+                &path("resolve_threshold"),
+                "must be on the non-firing side of threshold",
+            );
+            check(rule.for_evaluations > 0, &path("for"), "must be positive");
+            check(
+                rule.resolve_after > 0,
+                &path("resolve_after"),
+                "must be positive",
+            );
+            check(rule.interval > 0, &path("interval"), "must be positive");
+            check(
+                TimeWindow::parse(&rule.since, &rule.until, &self.time).is_ok(),
+                &path("since"),
+                "since and until must form a valid window",
+            );
+            check(
+                rule.k8s.validate().is_ok(),
+                &path("labels"),
+                "contains an invalid label name",
+            );
+            for (j, url) in rule.webhooks.iter().enumerate() {
+                check(
+                    url.starts_with("http://") || url.starts_with("https://"),
+                    &format!("alerts.rules[{}].webhooks[{}]", i, j),
+                    "must be an http:// or https:// URL",
+                );
+            }
+        }
+        for (i, slo) in self.slos.iter().enumerate() {
+            let path = |field: &str| format!("slos[{}].{}", i, field);
+            check(
+                !slo.name.trim().is_empty() && !slo.name.contains('/'),
+                &path("name"),
+                "must not be empty or contain /",
+            );
+            check(
+                self.slos.iter().filter(|s| s.name == slo.name).count() == 1,
+                &path("name"),
+                "must be unique",
+            );
+            for (field, query) in [("good", &slo.good), ("total", &slo.total)] {
+                check(
+                    matches!(nrql::event_types(query), Ok(types) if !types.is_empty()),
+                    &path(field),
+                    "must be NRQL with a FROM clause",
+                );
+                check(
+                    !nrql::has_clause(query, "SINCE") && !nrql::has_clause(query, "UNTIL"),
+                    &path(field),
+                    "must not have SINCE or UNTIL, enma adds the windows",
+                );
+            }
+            check(
+                slo.objective > 0.0 && slo.objective < 100.0,
+                &path("objective"),
+                "must be between 0 and 100, exclusive",
+            );
+            check(
+                slo.window_days > 0,
+                &path("window_days"),
+                "must be positive",
+            );
+            check(
+                slo.fast_burn_threshold > 0.0 && slo.slow_burn_threshold > 0.0,
+                &path("fast_burn_threshold"),
+                "burn thresholds must be positive",
+            );
+            check(
+                slo.cache_seconds > 0,
+                &path("cache_seconds"),
+                "must be positive",
+            );
+        }
+        check(
+            !matches!(&self.history.path, Some(p) if p.trim().is_empty()),
+            "history.path",
+            "must not be empty",
+        );
+        check(
+            self.history.max_results > 0,
+            "history.max_results",
+            "must be positive",
+        );
+        check(
+            self.history.max_file_mb > 0,
+            "history.max_file_mb",
+            "must be positive",
+        );
+        check(
+            self.stale_while_error.max_staleness > 0,
+            "stale_while_error.max_staleness",
+            "must be positive",
+        );
+        for (name, app) in &self.applications {
+            check(
+                !name.trim().is_empty(),
+                "applications",
+                "application names must not be empty",
+            );
+            for (field, value) in [
+                ("apm_name", &app.apm_name),
+                ("k8s_name", &app.k8s_name),
+                ("namespace", &app.namespace),
+                ("cluster_name", &app.cluster_name),
+            ] {
+                check(
+                    !matches!(value, Some(v) if v.trim().is_empty()),
+                    &format!("applications.{}.{}", name, field),
+                    "must not be empty",
+                );
+            }
+        }
+        for (i, client) in self.nrql.clients.iter().enumerate() {
+            check(
+                !client.token.trim().is_empty(),
+                &format!("nrql.clients[{}].token", i),
+                "must not be empty",
+            );
+            check(
+                !client.scopes.is_empty(),
+                &format!("nrql.clients[{}].scopes", i),
+                "must not be empty",
+            );
+            for (j, scope) in client.scopes.iter().enumerate() {
+                check(
+                    scope == "*" || self.nrql.allowed_event_types.contains(scope),
+                    &format!("nrql.clients[{}].scopes[{}]", i, j),
+                    "must be * or one of nrql.allowed_event_types",
+                );
+            }
+        }
+        for error in self.check_metric_templates() {
+            if !errors.iter().any(|e| e.path == error.path) {
+                errors.push(error);
+            }
+        }
+        errors
+    }
+
+    /// Renders every built-in metric query with the configured identity
+    /// attributes and application mappings, so a setting that would produce
+    /// NRQL New Relic can't parse fails here instead of on the first request.
+    /// Errors point at the setting the broken query came from.
+    fn check_metric_templates(&self) -> Vec<ValidationError> {
+        let mut errors: Vec<ValidationError> = Vec::new();
+        let apps = std::iter::once(None).chain(self.applications.keys().map(Some));
+        for app in apps {
+            for metric in Metric::ALL {
+                let target = self.resolve_target(
+                    metric,
+                    app.map_or("app", String::as_str),
+                    &K8sFilter::default(),
+                );
+                let query = metric.get_query(&target, "3600 seconds ago", "now");
+                let rendered = match nrql::event_types(&query) {
+                    Ok(types) if types.is_empty() => Err(String::from("has no FROM clause")),
+                    Ok(_) => nrql::since_window(&query).map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                let e = match rendered {
+                    Ok(()) => continue,
+                    Err(e) => e,
+                };
+                let path = match app {
+                    Some(name) => format!("applications.{}", name),
+                    None if self.identity.metrics.contains_key(metric.slug()) => {
+                        format!("identity.metrics.{}", metric.slug())
+                    }
+                    None if metric.k8s_attributes().is_some() => String::from("identity.k8s_label"),
+                    None => String::from("identity.apm_attribute"),
+                };
+                if !errors.iter().any(|error| error.path == path) {
+                    errors.push(ValidationError {
+                        path,
+                        message: format!("renders malformed NRQL for {}: {}", metric.slug(), e),
+                    });
+                }
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = "
+server: { host: 0.0.0.0, port: 8080 }
+newrelic: { api_key: NRIQ-key, account_id: 12345 }
+";
+
+    fn errors(yaml: &str) -> Vec<String> {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        config.validate().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_errors() {
+        assert!(errors(VALID).is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_reported_with_its_path() {
+        let errors = errors(
+            "
+server: { host: '', port: 0 }
+newrelic: { api_key: '<your api key>', account_id: 0, endpoint: insights-api.newrelic.com }
+time: { timezone: Mars/Olympus }
+applications:
+  checkout: { apm_name: checkout-api, namespace: '' }
+scheduler:
+  secret: s
+  jobs:
+    - { name: p95, metric: response-time-p42, application_name: checkout, interval: 0, webhooks: [ftp://hook] }
+",
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "server.host: must not be empty",
+                "server.port: must be between 1 and 65535",
+                "newrelic.api_key: still contains the example placeholder",
+                "newrelic.account_id: must be a positive integer",
+                "newrelic.endpoint: must be an http:// or https:// URL",
+                "time.timezone: must be an IANA timezone like Asia/Jakarta",
+                "scheduler.jobs[0].metric: is not a known metric",
+                "scheduler.jobs[0].interval: must be positive",
+                "scheduler.jobs[0].webhooks[0]: must be an http:// or https:// URL",
+                "applications.checkout.namespace: must not be empty",
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_metric_templates_are_reported() {
+        let errors = errors(&format!(
+            "{}identity: {{ apm_attribute: 'app`Name', metrics: {{ cpu-used-core: container.name }} }}",
+            VALID
+        ));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(
+            "identity.apm_attribute: renders malformed NRQL for response-time-average: unterminated ` quote"
+        ));
+    }
+
+    #[test]
+    fn numbers_out_of_range_fail_to_parse() {
+        let config = serde_yaml::from_str::<Config>(
+            "server: { host: 0.0.0.0, port: 70000 }\nnewrelic: { api_key: k, account_id: 1 }",
+        );
+        assert!(config.is_err());
+        let config = serde_yaml::from_str::<Config>(
+            "server: { host: 0.0.0.0, port: 8080 }\nnewrelic: { api_key: k, account_id: -1 }",
+        );
+        assert!(config.is_err());
     }
 }
@@ -0,0 +1,10 @@
+use {
+    crate::alerts::AlertStore,
+    actix_web::{get, web, HttpResponse},
+};
+
+/// Current status of every alert rule.
+#[get("/alerts")]
+async fn alerts(store: web::Data<AlertStore>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "alerts": store.snapshot() }))
+}
@@ -0,0 +1,17 @@
+use {
+    crate::shutdown::Lifecycle,
+    actix_web::{get, web, HttpResponse},
+};
+
+#[get("/health/live")]
+async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+}
+
+#[get("/health/ready")]
+async fn ready(lifecycle: web::Data<Lifecycle>) -> HttpResponse {
+    if lifecycle.is_ready() {
+        return HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }));
+    }
+    HttpResponse::ServiceUnavailable().json(serde_json::json!({ "status": "shutting down" }))
+}
@@ -0,0 +1,67 @@
+use {
+    crate::{
+        history::Filter,
+        log_ctx,
+        newrelic::{metric::Metric, window},
+        state::SharedState,
+    },
+    actix_web::{get, web, HttpResponse},
+    serde::Deserialize,
+    serde_json::json,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    application_name: Option<String>,
+    metric: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Recorded metric values, filtered by application, metric and fetch time.
+#[get("/history")]
+async fn history(query: web::Query<HistoryQuery>, state: web::Data<SharedState>) -> HttpResponse {
+    let state = state.load();
+    if !state.history.is_enabled() {
+        return HttpResponse::NotFound().json(json!({ "error": "history is disabled" }));
+    }
+    let metric = match &query.metric {
+        Some(name) => match Metric::from_name(name) {
+            Some(metric) => Some(metric.slug().to_string()),
+            None => {
+                return HttpResponse::BadRequest()
+                    .json(json!({ "error": format!("unknown metric {}", name) }))
+            }
+        },
+        None => None,
+    };
+    let time = |value: &Option<String>, default: &str, field: &str| {
+        window::parse_time(value.as_deref().unwrap_or(default), &state.config.time)
+            .map_err(|e| format!("{}: {}", field, e))
+    };
+    let (since, until) = match (
+        time(&query.since, "1 day ago", "since"),
+        time(&query.until, "now", "until"),
+    ) {
+        (Ok(since), Ok(until)) => (since, until),
+        (Err(e), _) | (_, Err(e)) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let max_results = state.config.history.max_results;
+    let filter = Filter {
+        application_name: query.application_name.clone(),
+        metric,
+        since,
+        until,
+        limit: query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, max_results),
+    };
+    match state.history.query(filter).await {
+        Ok(records) => HttpResponse::Ok().json(json!({ "records": records })),
+        Err(e) => {
+            log_ctx!(error, "{}", e);
+            HttpResponse::InternalServerError().json(json!({ "error": e }))
+        }
+    }
+}
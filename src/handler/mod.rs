@@ -1 +1,5 @@
+pub mod alerts;
+pub mod health;
+pub mod history;
+pub mod slo;
 pub mod v1;
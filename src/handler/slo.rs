@@ -0,0 +1,34 @@
+use {
+    crate::{
+        log::context,
+        log_ctx,
+        slo::{report, SloError},
+        state::SharedState,
+    },
+    actix_web::{get, web, HttpResponse},
+    serde_json::json,
+};
+
+/// SLI, error budget and burn rates of the SLO called `name`.
+#[get("/slo/{name}")]
+async fn slo(name: web::Path<String>, state: web::Data<SharedState>) -> HttpResponse {
+    let state = state.load();
+    context::update(|c| c.metric = Some("slo"));
+    let config = match state.config.slos.iter().find(|s| s.name == *name) {
+        Some(config) => config,
+        None => {
+            return HttpResponse::NotFound()
+                .json(json!({ "error": format!("unknown slo {}", name) }))
+        }
+    };
+    match report(&state.newrelic, &state.slo_cache, config).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            log_ctx!(error, "SLO {} could not be computed: {}", config.name, e);
+            match e {
+                SloError::Query(e) => HttpResponse::BadRequest().json(json!({ "error": e })),
+                SloError::Upstream(e) => HttpResponse::BadGateway().json(json!({ "error": e })),
+            }
+        }
+    }
+}
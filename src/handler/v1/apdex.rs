@@ -0,0 +1,20 @@
+use {
+    crate::handler::v1::{model, query},
+    crate::newrelic::metric::{Metric, DEFAULT_APDEX_T},
+    crate::state::SharedState,
+    actix_web::{post, web, HttpRequest, HttpResponse},
+    serde_json::json,
+};
+
+#[post("/apdex")]
+async fn apdex(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    let t = req.data.apdex_t.unwrap_or(DEFAULT_APDEX_T);
+    if t <= 0.0 {
+        return HttpResponse::BadRequest().json(json!({ "error": "apdex_t must be positive" }));
+    }
+    query::run(Metric::Apdex(t), &http_req, &req.data, &state).await
+}
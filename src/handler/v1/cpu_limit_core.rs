@@ -0,0 +1,15 @@
+use {
+    crate::handler::v1::{model, query},
+    crate::newrelic::metric::Metric,
+    crate::state::SharedState,
+    actix_web::{post, web, HttpRequest, HttpResponse},
+};
+
+#[post("/cpu-limit-core")]
+async fn cpu_limit_core(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    query::run(Metric::CpuLimitCore, &http_req, &req.data, &state).await
+}
@@ -0,0 +1,21 @@
+use {
+    crate::handler::v1::{model, query},
+    crate::newrelic::metric::Metric,
+    crate::state::SharedState,
+    actix_web::{post, web, HttpRequest, HttpResponse},
+};
+
+#[post("/memory-utilization-request")]
+async fn memory_utilization_request(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    query::run(
+        Metric::MemoryUtilizationRequest,
+        &http_req,
+        &req.data,
+        &state,
+    )
+    .await
+}
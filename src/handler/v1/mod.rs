@@ -1,8 +1,31 @@
+pub mod apdex;
+pub mod container_restarts;
+pub mod cpu_limit_core;
 pub mod cpu_requested_core;
+pub mod cpu_throttling_ratio;
 pub mod cpu_used_core;
+pub mod cpu_utilization_limit;
+pub mod cpu_utilization_request;
+pub mod error_rate;
+pub mod gc_frequency;
+pub mod gc_pause_time;
+pub mod loaded_class_count;
+pub mod memory_heap_committed;
+pub mod memory_heap_max;
 pub mod memory_heap_used;
+pub mod memory_heap_utilization;
+pub mod memory_limit;
+pub mod memory_metaspace_used;
+pub mod memory_requested;
+pub mod memory_utilization_limit;
+pub mod memory_utilization_request;
+pub mod memory_working_set;
 pub mod model;
+pub mod nrql;
+pub mod oom_killed;
+pub mod query;
 pub mod response_time_average;
+pub mod response_time_percentile;
 pub mod thread_count;
 pub mod throughput;
 pub mod total_pods;
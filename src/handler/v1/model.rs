@@ -1,10 +1,35 @@
-use serde::{Deserialize, Serialize};
+use {
+    crate::newrelic::{
+        filter::K8sFilter,
+        model::{
+            NewrelicApdexModel, NewrelicCompareResponseModel, NewrelicMetadataModel,
+            NewrelicPerformanceStatsModel, NewrelicResponseModel,
+        },
+        window,
+    },
+    serde::{Deserialize, Serialize},
+};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RequestData {
     pub application_name: String,
+    /// RFC 3339, epoch milliseconds, a local date/time or relative time like `1 hour ago`
+    #[serde(deserialize_with = "window::string_or_number")]
     pub start_time: String,
+    #[serde(deserialize_with = "window::string_or_number")]
     pub end_time: String,
+    /// Percentile for /response-time-percentile, between 0 and 100 exclusive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub percentile: Option<f32>,
+    /// Apdex T threshold in seconds for /apdex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apdex_t: Option<f32>,
+    /// Relative time to compare the window with, e.g. `1 week ago`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare_with: Option<String>,
+    /// namespace, cluster_name and labels for the Kubernetes metrics
+    #[serde(flatten)]
+    pub k8s: K8sFilter,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -12,32 +37,264 @@ pub struct Request {
     pub data: RequestData,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NrqlRequestData {
+    pub query: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NrqlRequest {
+    pub data: NrqlRequestData,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 
 struct ResponseData {
     result: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    apdex: Option<NewrelicApdexModel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comparison: Option<Comparison>,
+    /// Set when `result` is the last good value, served because New Relic was unavailable
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    /// Seconds since a stale `result` was fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_seconds: Option<u64>,
+}
+
+/// The metric for the requested window next to the window it was compared with.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Comparison {
+    pub current: f32,
+    pub previous: Option<f32>,
+    /// Change from previous to current in %, unset when previous is missing or zero
+    pub delta_percent: Option<f32>,
+}
+
+impl Comparison {
+    pub fn new(current: f32, previous: Option<f32>) -> Self {
+        let delta_percent = previous
+            .filter(|p| *p != 0.0)
+            .map(|p| (current - p) / p.abs() * 100.0);
+        Self {
+            current,
+            previous,
+            delta_percent,
+        }
+    }
+}
+
+/// What enma sent (or would send) to New Relic, returned in explain mode.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Explain {
+    pub nrql: String,
+    pub account_id: u32,
+    pub backend: String,
+    pub executed: bool,
+}
+
+/// What New Relic reported about evaluating the query.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Meta {
+    pub begin_time: Option<String>,
+    pub end_time: Option<String>,
+    pub inspected_count: Option<u64>,
+    pub omitted_count: Option<u64>,
+    pub matched_count: Option<u64>,
+    pub wall_clock_time_ms: Option<u64>,
+    pub warnings: Vec<String>,
+}
+
+impl Meta {
+    pub fn from_newrelic(res: &NewrelicResponseModel) -> Self {
+        Self::new(
+            res.get_metadata(),
+            res.get_performance_stats(),
+            res.warnings(),
+        )
+    }
+
+    pub fn from_comparison(res: &NewrelicCompareResponseModel) -> Self {
+        Self::new(
+            res.get_metadata(),
+            res.get_performance_stats(),
+            res.warnings(),
+        )
+    }
+
+    fn new(
+        metadata: &NewrelicMetadataModel,
+        stats: &NewrelicPerformanceStatsModel,
+        warnings: Vec<String>,
+    ) -> Self {
+        Self {
+            begin_time: metadata.get_begin_time().map(String::from),
+            end_time: metadata.get_end_time().map(String::from),
+            inspected_count: stats.get_inspected_count(),
+            omitted_count: stats.get_omitted_count(),
+            matched_count: stats.get_match_count(),
+            wall_clock_time_ms: stats.get_wall_clock_time(),
+            warnings,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Response {
     api_version: String,
-    data: ResponseData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<ResponseData>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explain: Option<Explain>,
 }
 
 impl Response {
+    pub fn get_result(&self) -> Option<f32> {
+        self.data.as_ref().map(|data| data.result)
+    }
+
     pub fn set_response(res: f32) -> Self {
-        let data = ResponseData { result: res };
-        return Self {
-            api_version: String::from("v1"),
-            data: data,
+        let data = ResponseData {
+            result: res,
+            apdex: None,
+            comparison: None,
+            stale: false,
+            age_seconds: None,
         };
+        Self {
+            api_version: String::from("v1"),
+            data: Some(data),
+            meta: None,
+            explain: None,
+        }
+    }
+
+    /// The last good value, served while New Relic is unavailable.
+    pub fn stale(res: f32, age_seconds: u64) -> Self {
+        let mut response = Self::set_response(res);
+        if let Some(data) = response.data.as_mut() {
+            data.stale = true;
+            data.age_seconds = Some(age_seconds);
+        }
+        response
     }
 
-    pub fn default() -> Self {
-        let data = ResponseData { result: 0.0 };
-        return Self {
+    /// Response for a dry run, carrying only the explanation.
+    pub fn explain_only(explain: Explain) -> Self {
+        Self {
             api_version: String::from("v1"),
-            data: data,
+            data: None,
+            meta: None,
+            explain: Some(explain),
+        }
+    }
+
+    /// Attaches the apdex breakdown next to the score in `result`.
+    pub fn with_apdex(mut self, apdex: Option<NewrelicApdexModel>) -> Self {
+        if let Some(data) = self.data.as_mut() {
+            data.apdex = apdex;
+        }
+        self
+    }
+
+    /// Sets the comparison from the current result and the previous window's value.
+    pub fn with_previous(mut self, previous: Option<f32>) -> Self {
+        if let Some(data) = self.data.as_mut() {
+            data.comparison = Some(Comparison::new(data.result, previous));
+        }
+        self
+    }
+
+    pub fn with_meta(mut self, meta: Option<Meta>) -> Self {
+        self.meta = meta;
+        self
+    }
+
+    pub fn with_explain(mut self, explain: Option<Explain>) -> Self {
+        self.explain = explain;
+        self
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        let data = ResponseData {
+            result: 0.0,
+            apdex: None,
+            comparison: None,
+            stale: false,
+            age_seconds: None,
         };
+        Self {
+            api_version: String::from("v1"),
+            data: Some(data),
+            meta: None,
+            explain: None,
+        }
+    }
+}
+
+/// New Relic's result list passed through as is, keyed by whatever aggregate functions the query used.
+#[derive(Serialize, Debug)]
+pub struct NrqlResponseData {
+    pub results: serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NrqlResponse {
+    api_version: String,
+    data: NrqlResponseData,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    meta: Option<Meta>,
+}
+
+impl NrqlResponse {
+    pub fn new(results: serde_json::Value, meta: Option<Meta>) -> Self {
+        Self {
+            api_version: String::from("v1"),
+            data: NrqlResponseData { results },
+            meta,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_is_relative_to_previous() {
+        let comparison = Comparison::new(120.0, Some(100.0));
+        assert_eq!(comparison.delta_percent, Some(20.0));
+        assert_eq!(
+            Comparison::new(75.0, Some(100.0)).delta_percent,
+            Some(-25.0)
+        );
+    }
+
+    #[test]
+    fn negative_previous_keeps_the_direction() {
+        // -50 to -25 went up, so the delta is positive
+        assert_eq!(
+            Comparison::new(-25.0, Some(-50.0)).delta_percent,
+            Some(50.0)
+        );
+    }
+
+    #[test]
+    fn no_delta_without_a_usable_previous() {
+        assert_eq!(Comparison::new(10.0, None).delta_percent, None);
+        assert_eq!(Comparison::new(10.0, Some(0.0)).delta_percent, None);
+    }
+
+    #[test]
+    fn with_previous_compares_against_result() {
+        let response = Response::set_response(30.0).with_previous(Some(40.0));
+        let comparison = response.data.unwrap().comparison.unwrap();
+        assert_eq!(comparison.current, 30.0);
+        assert_eq!(comparison.delta_percent, Some(-25.0));
     }
 }
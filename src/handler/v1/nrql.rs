@@ -0,0 +1,218 @@
+use {
+    crate::config::{NrqlClientConfig, NrqlConfig},
+    crate::handler::v1::model,
+    crate::newrelic::{model::NewrelicResponseModel, nrql},
+    crate::{log::context, log_ctx, state::SharedState},
+    actix_web::{http::header, post, web, HttpRequest, HttpResponse},
+    serde_json::json,
+};
+
+#[post("/nrql")]
+async fn run_nrql(
+    http_req: HttpRequest,
+    req: web::Json<model::NrqlRequest>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    let state = state.load();
+    let guard = &state.config.nrql;
+    if !guard.enabled {
+        return HttpResponse::NotFound().json(json!({ "error": "nrql passthrough is disabled" }));
+    }
+    let client = match authorize(&http_req, guard) {
+        Some(c) => c,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(json!({ "error": "missing or unknown bearer token" }))
+        }
+    };
+    let query = req.data.query.trim();
+    context::update(|c| c.metric = Some("nrql"));
+    if let Err(e) = check_query(query, guard, client) {
+        log_ctx!(warn, "Rejected NRQL from client {}: {}", client.name, e);
+        return HttpResponse::Forbidden().json(json!({ "error": e }));
+    }
+    log_ctx!(info, "Running NRQL for client {}", client.name);
+    match state.newrelic.run_nrql(query, "nrql").await {
+        Ok(raw) => match raw.get("error") {
+            Some(e) => {
+                log_ctx!(error, "{:?}", e);
+                HttpResponse::BadRequest().json(json!({ "error": e }))
+            }
+            None => {
+                let meta = serde_json::from_value::<NewrelicResponseModel>(raw.clone())
+                    .ok()
+                    .map(|res| model::Meta::from_newrelic(&res));
+                for warning in meta.iter().flat_map(|m| m.warnings.iter()) {
+                    log_ctx!(
+                        warn,
+                        "New Relic warning for client {}: {}",
+                        client.name,
+                        warning
+                    );
+                }
+                HttpResponse::Ok().json(model::NrqlResponse::new(
+                    raw.get("results").cloned().unwrap_or_else(|| json!([])),
+                    meta,
+                ))
+            }
+        },
+        Err(e) => {
+            log_ctx!(error, "{:?}", e);
+            HttpResponse::BadGateway().json(json!({ "error": e.to_string() }))
+        }
+    }
+}
+
+/// Finds the client whose token matches the request's bearer token.
+fn authorize<'a>(req: &HttpRequest, guard: &'a NrqlConfig) -> Option<&'a NrqlClientConfig> {
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))?;
+    guard
+        .clients
+        .iter()
+        .find(|c| constant_time_eq(c.token.as_bytes(), token.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Checks the query's event types against the allowlist and the client's
+/// scopes, and how far back it reaches (SINCE plus any COMPARE WITH offset)
+/// against the configured maximum.
+fn check_query(query: &str, guard: &NrqlConfig, client: &NrqlClientConfig) -> Result<(), String> {
+    let event_types = nrql::event_types(query)?;
+    if event_types.is_empty() {
+        return Err(String::from("query has no FROM clause"));
+    }
+    for event_type in &event_types {
+        if !guard.allowed_event_types.contains(event_type) {
+            return Err(format!("event type {} is not allowed", event_type));
+        }
+        if !client.scopes.iter().any(|s| s == "*" || s == event_type) {
+            return Err(format!(
+                "client {} is not allowed to query {}",
+                client.name, event_type
+            ));
+        }
+    }
+    let reach = nrql::reach(query)?;
+    if reach.as_secs() > guard.max_since_minutes * 60 {
+        return Err(format!(
+            "query reaches back {} minutes, the maximum is {}",
+            reach.as_secs() / 60,
+            guard.max_since_minutes
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, actix_web::test::TestRequest};
+
+    fn guard() -> NrqlConfig {
+        NrqlConfig {
+            enabled: true,
+            allowed_event_types: vec![String::from("Transaction"), String::from("Log")],
+            max_since_minutes: 60,
+            clients: vec![client("Transaction")],
+        }
+    }
+
+    fn client(scope: &str) -> NrqlClientConfig {
+        NrqlClientConfig {
+            name: String::from("grafana"),
+            token: String::from("s3cret"),
+            scopes: vec![String::from(scope)],
+        }
+    }
+
+    #[test]
+    fn disallowed_event_type_is_rejected() {
+        let e = check_query(
+            "SELECT count(*) FROM PageView SINCE 5 minutes ago",
+            &guard(),
+            &client("*"),
+        )
+        .unwrap_err();
+        assert_eq!(e, "event type PageView is not allowed");
+    }
+
+    #[test]
+    fn event_type_outside_client_scopes_is_rejected() {
+        let e = check_query(
+            "SELECT count(*) FROM Log SINCE 5 minutes ago",
+            &guard(),
+            &client("Transaction"),
+        )
+        .unwrap_err();
+        assert_eq!(e, "client grafana is not allowed to query Log");
+        assert!(check_query(
+            "SELECT count(*) FROM Log SINCE 5 minutes ago",
+            &guard(),
+            &client("*")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn since_over_the_maximum_is_rejected() {
+        let client = client("Transaction");
+        assert!(check_query(
+            "SELECT count(*) FROM Transaction SINCE 60 minutes ago",
+            &guard(),
+            &client
+        )
+        .is_ok());
+        let e = check_query(
+            "SELECT count(*) FROM Transaction SINCE 61 minutes ago",
+            &guard(),
+            &client,
+        )
+        .unwrap_err();
+        assert_eq!(e, "query reaches back 61 minutes, the maximum is 60");
+    }
+
+    #[test]
+    fn compare_with_counts_towards_the_maximum() {
+        let e = check_query(
+            "SELECT count(*) FROM Transaction SINCE 5 minutes ago COMPARE WITH 52 weeks ago",
+            &guard(),
+            &client("Transaction"),
+        )
+        .unwrap_err();
+        assert_eq!(e, "query reaches back 524165 minutes, the maximum is 60");
+        assert!(check_query(
+            "SELECT count(*) FROM Transaction SINCE 30 minutes ago COMPARE WITH 30 minutes ago",
+            &guard(),
+            &client("Transaction")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn authorize_needs_a_known_bearer_token() {
+        let guard = guard();
+        let req = TestRequest::default().to_http_request();
+        assert!(authorize(&req, &guard).is_none());
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer wrong"))
+            .to_http_request();
+        assert!(authorize(&req, &guard).is_none());
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "s3cret"))
+            .to_http_request();
+        assert!(authorize(&req, &guard).is_none());
+        let req = TestRequest::default()
+            .insert_header((header::AUTHORIZATION, "Bearer s3cret"))
+            .to_http_request();
+        assert_eq!(
+            authorize(&req, &guard).map(|c| c.name.as_str()),
+            Some("grafana")
+        );
+    }
+}
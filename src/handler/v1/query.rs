@@ -0,0 +1,367 @@
+use {
+    crate::handler::v1::model,
+    crate::history::Record,
+    crate::newrelic::{
+        metric::Metric,
+        model::{NewrelicQueryResult, NewrelicResponseModel},
+        newrelic::Newrelic,
+        window::{self, TimeWindow},
+    },
+    crate::{log::context, log_ctx, state::SharedState, telemetry::metrics::STALE_RESPONSES},
+    actix_web::{
+        http::{header, StatusCode},
+        web, HttpRequest, HttpResponse,
+    },
+    serde::Deserialize,
+    serde_json::json,
+    std::time::Duration,
+    tracing::{field, instrument, Span},
+};
+
+pub const EXPLAIN_HEADER: &str = "x-enma-explain";
+
+/// RFC 7234 warning sent with values served by stale-while-error.
+const STALE_WARNING: &str = "110 enma \"Response is Stale\"";
+
+/// How much of the query to explain, from `?explain=` or the `X-Enma-Explain` header:
+/// `true` returns the NRQL without running it, `run` runs it and returns both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExplainMode {
+    Off,
+    DryRun,
+    Run,
+}
+
+#[derive(Deserialize)]
+struct ExplainQuery {
+    explain: Option<String>,
+}
+
+impl ExplainMode {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let from_query = web::Query::<ExplainQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|q| q.into_inner().explain);
+        let from_header = req
+            .headers()
+            .get(EXPLAIN_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        match from_query.or(from_header).as_deref() {
+            Some("true") | Some("1") | Some("dry-run") => Self::DryRun,
+            Some("run") => Self::Run,
+            _ => Self::Off,
+        }
+    }
+}
+
+/// Runs `metric` for the request and maps the outcome to the v1 response,
+/// shared by every metric handler.
+// HttpResponse implements Future in actix-web 4 beta, which trips this lint on `instrument`.
+#[allow(clippy::async_yields_async)]
+#[instrument(
+    name = "metric_handler",
+    skip(req, data, state),
+    fields(
+        metric = metric.name(),
+        application_name = %data.application_name,
+        value = field::Empty,
+    )
+)]
+pub async fn run(
+    metric: Metric,
+    req: &HttpRequest,
+    data: &model::RequestData,
+    state: &SharedState,
+) -> HttpResponse {
+    context::update(|c| {
+        c.application_name = Some(data.application_name.clone());
+        c.metric = Some(metric.name());
+    });
+    if let Err(e) = data.k8s.validate() {
+        return HttpResponse::BadRequest().json(json!({ "error": e }));
+    }
+    let last_good = state.last_good();
+    let state = state.load();
+    let window = match TimeWindow::parse(&data.start_time, &data.end_time, &state.config.time) {
+        Ok(window) => window,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    let target = state
+        .config
+        .resolve_target(&metric, &data.application_name, &data.k8s);
+    let mut nrql = metric.get_query(&target, &window.since, &window.until);
+    if let Some(compare_with) = &data.compare_with {
+        match window::compare_with(compare_with) {
+            Ok(clause) => nrql.push_str(&clause),
+            Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+        }
+    }
+    let mode = ExplainMode::from_request(req);
+    let explain = |executed| model::Explain {
+        nrql: nrql.clone(),
+        account_id: state.newrelic.get_account_id(),
+        backend: state.newrelic.get_endpoint().to_string(),
+        executed,
+    };
+    if mode == ExplainMode::DryRun {
+        return HttpResponse::Ok().json(model::Response::explain_only(explain(false)));
+    }
+    let (status, response) = execute(metric, &nrql, data, &state.newrelic).await;
+    if let (StatusCode::OK, Some(value)) = (status, response.get_result()) {
+        state
+            .history
+            .record(Record::new(
+                &data.application_name,
+                metric.slug(),
+                window.start,
+                window.end,
+                value,
+                "api",
+            ))
+            .await;
+    }
+    let stale_config = &state.config.stale_while_error;
+    let key = last_good_key(metric, &nrql);
+    let explain = match mode {
+        ExplainMode::Run => Some(explain(true)),
+        _ => None,
+    };
+    match (status, response.get_result()) {
+        (StatusCode::OK, Some(value)) if stale_config.enabled => last_good.remember(key, value),
+        (StatusCode::BAD_GATEWAY, _) if stale_config.enabled => {
+            let max_age = Duration::from_secs(stale_config.max_staleness);
+            if let Some((value, age)) = last_good.get(&key, max_age) {
+                log_ctx!(
+                    warn,
+                    "New Relic unavailable, serving a {}s old value for service: {}, and metric: {:?}",
+                    age.as_secs(),
+                    data.application_name.as_str(),
+                    metric
+                );
+                STALE_RESPONSES.with_label_values(&[metric.name()]).inc();
+                return HttpResponse::Ok()
+                    .insert_header((header::WARNING, STALE_WARNING))
+                    .json(model::Response::stale(value, age.as_secs()).with_explain(explain));
+            }
+        }
+        _ => {}
+    }
+    HttpResponse::build(status).json(response.with_explain(explain))
+}
+
+/// Identifies a value for stale-while-error: the metric and its rendered NRQL,
+/// which carries the app, filters, window and COMPARE WITH. Relative windows
+/// render as `<n> seconds ago`, so repeated requests for them share a key.
+fn last_good_key(metric: Metric, nrql: &str) -> String {
+    format!("{:?}|{}", metric, nrql)
+}
+
+async fn execute(
+    metric: Metric,
+    nrql: &str,
+    data: &model::RequestData,
+    newrelic: &Newrelic,
+) -> (StatusCode, model::Response) {
+    match newrelic.go_query(metric, nrql).await {
+        Ok(result) => match result {
+            NewrelicQueryResult::Ok(res) => {
+                let meta = model::Meta::from_newrelic(&res);
+                log_warnings(metric, &meta, data);
+                let (status, response) = extract(metric, &res, data);
+                (status, response.with_meta(Some(meta)))
+            }
+            NewrelicQueryResult::Compare(res) => {
+                let meta = model::Meta::from_comparison(&res);
+                log_warnings(metric, &meta, data);
+                let (status, mut response) = extract(metric, res.get_current(), data);
+                if status == StatusCode::OK {
+                    response = response.with_previous(metric.extract(res.get_previous()));
+                }
+                (status, response.with_meta(Some(meta)))
+            }
+            NewrelicQueryResult::Err(e) => {
+                log_ctx!(error, "{:?}", e.get_error_msg());
+                (StatusCode::BAD_REQUEST, model::Response::default())
+            }
+        },
+        Err(e) => {
+            log_ctx!(error, "{:?}", e);
+            (StatusCode::BAD_GATEWAY, model::Response::default())
+        }
+    }
+}
+
+fn log_warnings(metric: Metric, meta: &model::Meta, data: &model::RequestData) {
+    for warning in &meta.warnings {
+        log_ctx!(
+            warn,
+            "New Relic warning for service: {}, and metric: {:?}: {}",
+            data.application_name.as_str(),
+            metric,
+            warning
+        );
+    }
+}
+
+fn extract(
+    metric: Metric,
+    res: &NewrelicResponseModel,
+    data: &model::RequestData,
+) -> (StatusCode, model::Response) {
+    match metric.extract(res) {
+        Some(value) => {
+            if metric.zero_is_missing() && value.eq(&0.0) {
+                log_ctx!(
+                    warn,
+                    "Returning zero from newrelic with service: {}, and metric: {:?}",
+                    data.application_name.as_str(),
+                    metric
+                );
+                return (StatusCode::NOT_FOUND, model::Response::default());
+            }
+            Span::current().record("value", value);
+            let apdex = match metric {
+                Metric::Apdex(_) => res.get_apdex(),
+                _ => None,
+            };
+            (
+                StatusCode::OK,
+                model::Response::set_response(value).with_apdex(apdex),
+            )
+        }
+        None => {
+            log_ctx!(
+                warn,
+                "Returning null from newrelic with service: {}, and metric: {:?}",
+                data.application_name.as_str(),
+                metric
+            );
+            (StatusCode::NOT_FOUND, model::Response::default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{config::Config, state::AppState},
+        actix_web::{test, App, HttpServer},
+        std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    /// Local New Relic answering with an average response time of 0.25 while
+    /// `down` is false and with 503 once it is set.
+    // HttpResponse implements Future in actix-web 4 beta, which trips this lint.
+    #[allow(clippy::async_yields_async)]
+    fn newrelic(down: Arc<AtomicBool>) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = HttpServer::new(move || {
+            let down = down.clone();
+            App::new().default_service(web::to(move || {
+                let down = down.load(Ordering::SeqCst);
+                async move {
+                    if down {
+                        HttpResponse::ServiceUnavailable().finish()
+                    } else {
+                        HttpResponse::Ok().json(json!({ "results": [{ "result": 0.25 }] }))
+                    }
+                }
+            }))
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+        actix_web::rt::spawn(async move {
+            let _ = server.await;
+        });
+        endpoint
+    }
+
+    fn state(endpoint: &str, max_staleness: u64) -> SharedState {
+        let config: Config = serde_yaml::from_str(&format!(
+            "newrelic: {{ api_key: x, account_id: 1, endpoint: '{}' }}
+server: {{ host: 127.0.0.1, port: 0 }}
+stale_while_error: {{ enabled: true, max_staleness: {} }}",
+            endpoint, max_staleness
+        ))
+        .unwrap();
+        SharedState::new(AppState::new(config))
+    }
+
+    fn data(start_time: &str, compare_with: Option<&str>) -> model::RequestData {
+        serde_json::from_value(json!({
+            "application_name": "checkout",
+            "start_time": start_time,
+            "end_time": "now",
+            "compare_with": compare_with,
+        }))
+        .unwrap()
+    }
+
+    async fn call(
+        state: &SharedState,
+        data: &model::RequestData,
+    ) -> (StatusCode, Option<String>, serde_json::Value) {
+        let req = test::TestRequest::default().to_http_request();
+        let res = run(Metric::ResponseTimeAverage, &req, data, state).await;
+        let status = res.status();
+        let warning = res
+            .headers()
+            .get(header::WARNING)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let body = test::read_body(test::TestRequest::default().to_srv_response(res)).await;
+        let body = serde_json::from_slice(&body).unwrap_or_default();
+        (status, warning, body)
+    }
+
+    #[actix_rt::test]
+    async fn last_good_value_is_served_while_new_relic_is_down() {
+        let down = Arc::new(AtomicBool::new(false));
+        let state = state(&newrelic(down.clone()), 300);
+        let (status, warning, _) = call(&state, &data("5 minutes ago", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(warning, None);
+
+        down.store(true, Ordering::SeqCst);
+        let (status, warning, body) = call(&state, &data("5 minutes ago", None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(warning.as_deref(), Some(STALE_WARNING));
+        assert_eq!(body["data"]["result"], 0.25);
+        assert_eq!(body["data"]["stale"], true);
+    }
+
+    #[actix_rt::test]
+    async fn other_windows_and_comparisons_get_no_stale_value() {
+        let down = Arc::new(AtomicBool::new(false));
+        let state = state(&newrelic(down.clone()), 300);
+        call(&state, &data("5 minutes ago", None)).await;
+
+        down.store(true, Ordering::SeqCst);
+        let (status, warning, _) = call(&state, &data("1 hour ago", None)).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(warning, None);
+        let (status, _, _) = call(&state, &data("5 minutes ago", Some("1 week ago"))).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    #[actix_rt::test]
+    async fn error_is_returned_once_the_value_is_too_old() {
+        let down = Arc::new(AtomicBool::new(false));
+        let state = state(&newrelic(down.clone()), 1);
+        call(&state, &data("5 minutes ago", None)).await;
+
+        down.store(true, Ordering::SeqCst);
+        actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+        let (status, warning, _) = call(&state, &data("5 minutes ago", None)).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(warning, None);
+    }
+}
@@ -0,0 +1,87 @@
+use {
+    crate::handler::v1::{model, query},
+    crate::newrelic::metric::{is_valid_percentile, Metric, DEFAULT_PERCENTILE},
+    crate::state::SharedState,
+    actix_web::{post, web, HttpRequest, HttpResponse},
+    serde_json::json,
+};
+
+#[post("/response-time-p50")]
+async fn response_time_p50(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    query::run(
+        Metric::ResponseTimePercentile(50.0),
+        &http_req,
+        &req.data,
+        &state,
+    )
+    .await
+}
+
+#[post("/response-time-p90")]
+async fn response_time_p90(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    query::run(
+        Metric::ResponseTimePercentile(90.0),
+        &http_req,
+        &req.data,
+        &state,
+    )
+    .await
+}
+
+#[post("/response-time-p95")]
+async fn response_time_p95(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    query::run(
+        Metric::ResponseTimePercentile(95.0),
+        &http_req,
+        &req.data,
+        &state,
+    )
+    .await
+}
+
+#[post("/response-time-p99")]
+async fn response_time_p99(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    query::run(
+        Metric::ResponseTimePercentile(99.0),
+        &http_req,
+        &req.data,
+        &state,
+    )
+    .await
+}
+
+#[post("/response-time-percentile")]
+async fn response_time_percentile(
+    http_req: HttpRequest,
+    req: web::Json<model::Request>,
+    state: web::Data<SharedState>,
+) -> HttpResponse {
+    let percentile = req.data.percentile.unwrap_or(DEFAULT_PERCENTILE);
+    if !is_valid_percentile(percentile) {
+        return HttpResponse::BadRequest()
+            .json(json!({ "error": "percentile must be between 0 and 100 exclusive" }));
+    }
+    query::run(
+        Metric::ResponseTimePercentile(percentile),
+        &http_req,
+        &req.data,
+        &state,
+    )
+    .await
+}
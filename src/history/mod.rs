@@ -0,0 +1,328 @@
+use {
+    crate::config::HistoryConfig,
+    chrono::{DateTime, Utc},
+    log::error,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::VecDeque,
+        fs::{self, File, OpenOptions},
+        io::{BufRead, BufReader, ErrorKind, Write},
+        sync::Arc,
+    },
+    tokio::{sync::Mutex, task},
+};
+
+/// One fetched metric value, as stored in the history file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub application_name: String,
+    pub metric: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub value: f32,
+    /// What fetched it: api, scheduler or alert
+    pub source: String,
+    pub fetched_at: String,
+}
+
+impl Record {
+    pub fn new(
+        application_name: &str,
+        metric: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        value: f32,
+        source: &str,
+    ) -> Self {
+        Self {
+            application_name: application_name.to_string(),
+            metric: metric.to_string(),
+            start_time: start.to_rfc3339(),
+            end_time: end.to_rfc3339(),
+            value,
+            source: source.to_string(),
+            fetched_at: Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Which records `History::query` returns.
+pub struct Filter {
+    pub application_name: Option<String>,
+    pub metric: Option<String>,
+    pub since: DateTime<Utc>,
+    pub until: DateTime<Utc>,
+    pub limit: usize,
+}
+
+impl Filter {
+    fn matches(&self, record: &Record) -> bool {
+        let fetched_at = match DateTime::parse_from_rfc3339(&record.fetched_at) {
+            Ok(time) => time.with_timezone(&Utc),
+            Err(_) => return false,
+        };
+        fetched_at >= self.since
+            && fetched_at <= self.until
+            && self
+                .application_name
+                .iter()
+                .all(|app| &record.application_name == app)
+            && self.metric.iter().all(|m| &record.metric == m)
+    }
+}
+
+/// JSON lines file of every value enma fetched, off unless `history.path` is
+/// set. Once the file passes `history.max_file_mb` it is moved to `<path>.1`,
+/// replacing the previous one, so at most twice that is kept on disk and read
+/// by a query. File access runs on tokio's blocking pool.
+pub struct History {
+    path: Option<String>,
+    max_file_bytes: u64,
+    writer: Arc<Mutex<()>>,
+}
+
+impl History {
+    pub fn new(config: &HistoryConfig) -> Self {
+        Self {
+            path: config.path.clone(),
+            max_file_bytes: config.max_file_mb * 1024 * 1024,
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Appends `record`. Failures are logged and never fail the fetch.
+    pub async fn record(&self, record: Record) {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+        let line = match serde_json::to_string(&record) {
+            Ok(line) => line + "\n",
+            Err(e) => {
+                error!("Could not encode history record: {}", e);
+                return;
+            }
+        };
+        let _guard = self.writer.lock().await;
+        let max_file_bytes = self.max_file_bytes;
+        let written = task::spawn_blocking(move || append(&path, &line, max_file_bytes)).await;
+        match written {
+            Ok(Err(e)) => error!("{}", e),
+            Err(e) => error!("History write did not finish: {}", e),
+            Ok(Ok(())) => {}
+        }
+    }
+
+    /// The latest `limit` records matching `filter`, oldest first.
+    pub async fn query(&self, filter: Filter) -> Result<Vec<Record>, String> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => return Ok(Vec::new()),
+        };
+        task::spawn_blocking(move || scan(&path, &filter))
+            .await
+            .map_err(|e| format!("history query did not finish: {}", e))?
+    }
+}
+
+fn rotated(path: &str) -> String {
+    format!("{}.1", path)
+}
+
+/// Appends `line` to `path`, first moving the file to `<path>.1` if it has
+/// reached `max_file_bytes`.
+fn append(path: &str, line: &str, max_file_bytes: u64) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() >= max_file_bytes => fs::rename(path, rotated(path))
+            .map_err(|e| format!("Could not rotate history file {}: {}", path, e))?,
+        _ => {}
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .map_err(|e| format!("Could not write to history file {}: {}", path, e))
+}
+
+/// Reads the rotated file and then the current one, keeping the latest
+/// `filter.limit` matches.
+fn scan(path: &str, filter: &Filter) -> Result<Vec<Record>, String> {
+    let mut records = VecDeque::with_capacity(filter.limit);
+    for path in [rotated(path), path.to_string()] {
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("could not read history file {}: {}", path, e)),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| format!("could not read history file {}: {}", path, e))?;
+            // a line cut short by a crash is skipped rather than failing the query
+            let record = match serde_json::from_str::<Record>(&line) {
+                Ok(record) => record,
+                Err(_) => continue,
+            };
+            if !filter.matches(&record) {
+                continue;
+            }
+            if records.len() == filter.limit {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+    }
+    Ok(records.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, chrono::Duration};
+
+    /// A history file under the temp dir, removed with its rotated copy on drop.
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "enma-history-{}-{}.jsonl",
+                name,
+                std::process::id()
+            ));
+            let file = Self(path.to_string_lossy().into_owned());
+            file.remove();
+            file
+        }
+
+        fn remove(&self) {
+            let _ = fs::remove_file(&self.0);
+            let _ = fs::remove_file(rotated(&self.0));
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            self.remove();
+        }
+    }
+
+    fn history(file: &TempFile, max_file_mb: u64) -> History {
+        History::new(&HistoryConfig {
+            path: Some(file.0.clone()),
+            max_results: 1000,
+            max_file_mb,
+        })
+    }
+
+    fn record(app: &str, metric: &str, value: f32, fetched_at: DateTime<Utc>) -> Record {
+        let mut record = Record::new(app, metric, fetched_at, fetched_at, value, "api");
+        record.fetched_at = fetched_at.to_rfc3339();
+        record
+    }
+
+    fn filter(app: Option<&str>, metric: Option<&str>, limit: usize) -> Filter {
+        Filter {
+            application_name: app.map(String::from),
+            metric: metric.map(String::from),
+            since: Utc::now() - Duration::hours(1),
+            until: Utc::now(),
+            limit,
+        }
+    }
+
+    fn values(records: &[Record]) -> Vec<f32> {
+        records.iter().map(|r| r.value).collect()
+    }
+
+    #[tokio::test]
+    async fn query_filters_by_app_metric_and_time() {
+        let file = TempFile::new("filter");
+        let history = history(&file, 64);
+        let now = Utc::now();
+        history.record(record("checkout", "apdex", 1.0, now)).await;
+        history
+            .record(record("checkout", "throughput", 2.0, now))
+            .await;
+        history.record(record("cart", "apdex", 3.0, now)).await;
+        history
+            .record(record("checkout", "apdex", 4.0, now - Duration::hours(2)))
+            .await;
+
+        let records = history.query(filter(None, None, 10)).await.unwrap();
+        assert_eq!(values(&records), vec![1.0, 2.0, 3.0]);
+        let records = history
+            .query(filter(Some("checkout"), None, 10))
+            .await
+            .unwrap();
+        assert_eq!(values(&records), vec![1.0, 2.0]);
+        let records = history
+            .query(filter(Some("checkout"), Some("apdex"), 10))
+            .await
+            .unwrap();
+        assert_eq!(values(&records), vec![1.0]);
+    }
+
+    #[tokio::test]
+    async fn limit_keeps_the_latest_records() {
+        let file = TempFile::new("limit");
+        let history = history(&file, 64);
+        for value in 0..5 {
+            history
+                .record(record("checkout", "apdex", value as f32, Utc::now()))
+                .await;
+        }
+        let records = history.query(filter(None, None, 2)).await.unwrap();
+        assert_eq!(values(&records), vec![3.0, 4.0]);
+    }
+
+    #[tokio::test]
+    async fn corrupt_lines_are_skipped() {
+        let file = TempFile::new("corrupt");
+        let history = history(&file, 64);
+        history
+            .record(record("checkout", "apdex", 1.0, Utc::now()))
+            .await;
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&file.0)
+            .and_then(|mut f| f.write_all(b"not json\n{\"application_name\":\"chec\n"))
+            .unwrap();
+        history
+            .record(record("checkout", "apdex", 2.0, Utc::now()))
+            .await;
+        let records = history.query(filter(None, None, 10)).await.unwrap();
+        assert_eq!(values(&records), vec![1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn missing_file_is_empty() {
+        let file = TempFile::new("missing");
+        let records = history(&file, 64)
+            .query(filter(None, None, 10))
+            .await
+            .unwrap();
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn full_file_is_rotated_and_still_read() {
+        let file = TempFile::new("rotate");
+        let line = |value: f32| {
+            serde_json::to_string(&record("checkout", "apdex", value, Utc::now())).unwrap() + "\n"
+        };
+        append(&file.0, &line(1.0), u64::MAX).unwrap();
+        append(&file.0, &line(2.0), u64::MAX).unwrap();
+        assert!(fs::metadata(rotated(&file.0)).is_err());
+        append(&file.0, &line(3.0), line(1.0).len() as u64).unwrap();
+        assert!(fs::metadata(rotated(&file.0)).is_ok());
+        let records = scan(&file.0, &filter(None, None, 10)).unwrap();
+        assert_eq!(values(&records), vec![1.0, 2.0, 3.0]);
+        // the next rotation drops the oldest file
+        append(&file.0, &line(4.0), line(1.0).len() as u64).unwrap();
+        let records = scan(&file.0, &filter(None, None, 10)).unwrap();
+        assert_eq!(values(&records), vec![3.0, 4.0]);
+    }
+}
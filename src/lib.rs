@@ -1,6 +1,14 @@
+pub mod alerts;
 pub mod cli;
 pub mod config;
 pub mod handler;
+pub mod history;
 pub mod log;
 pub mod newrelic;
+pub mod reload;
+pub mod scheduler;
+pub mod shutdown;
+pub mod slo;
 pub mod startup;
+pub mod state;
+pub mod telemetry;
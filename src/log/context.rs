@@ -0,0 +1,105 @@
+use {
+    actix_web::{dev::ServiceRequest, http::HeaderValue},
+    std::{cell::RefCell, future::Future},
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static CONTEXT: RefCell<LogContext>;
+}
+
+/// Per-request fields attached to every log line emitted through `log_ctx!`,
+/// as MDC entries (`{X(request_id)}` in patterns, the `mdc` object in JSON).
+#[derive(Clone, Default, Debug)]
+pub struct LogContext {
+    pub request_id: String,
+    pub application_name: Option<String>,
+    pub metric: Option<&'static str>,
+    pub nrql_hash: Option<String>,
+    pub upstream_status: Option<u16>,
+    pub duration_ms: Option<u128>,
+}
+
+impl LogContext {
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![("request_id", self.request_id.clone())];
+        if let Some(v) = &self.application_name {
+            fields.push(("application_name", v.clone()));
+        }
+        if let Some(v) = self.metric {
+            fields.push(("metric", v.to_string()));
+        }
+        if let Some(v) = &self.nrql_hash {
+            fields.push(("nrql_hash", v.clone()));
+        }
+        if let Some(v) = self.upstream_status {
+            fields.push(("upstream_status", v.to_string()));
+        }
+        if let Some(v) = self.duration_ms {
+            fields.push(("duration_ms", v.to_string()));
+        }
+        fields
+    }
+}
+
+/// Takes the caller's `X-Request-Id` if it looks sane, otherwise generates one,
+/// and writes it back on the request so inner middleware sees the same id.
+pub fn request_id(req: &mut ServiceRequest) -> String {
+    let incoming = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= 128 && v.bytes().all(|b| b.is_ascii_graphic()))
+        .map(String::from);
+    let id = incoming.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        req.headers_mut().insert(
+            actix_web::http::header::HeaderName::from_static(REQUEST_ID_HEADER),
+            value,
+        );
+    }
+    id
+}
+
+/// Runs `f` with `context` as the current request's log context.
+pub async fn scope<F: Future>(context: LogContext, f: F) -> F::Output {
+    CONTEXT.scope(RefCell::new(context), f).await
+}
+
+/// Updates the current request's log context; a no-op outside a request.
+pub fn update(f: impl FnOnce(&mut LogContext)) {
+    let _ = CONTEXT.try_with(|c| f(&mut c.borrow_mut()));
+}
+
+/// Exposes the current context as MDC entries for the duration of `f`.
+pub fn with_mdc<R>(f: impl FnOnce() -> R) -> R {
+    let fields = CONTEXT
+        .try_with(|c| c.borrow().fields())
+        .unwrap_or_default();
+    for (k, v) in &fields {
+        log_mdc::insert(*k, v.as_str());
+    }
+    let result = f();
+    for (k, _) in &fields {
+        log_mdc::remove(*k);
+    }
+    result
+}
+
+/// Stable short hash of a rendered NRQL query (FNV-1a), so log lines can be
+/// grouped by query without logging the full text every time.
+pub fn nrql_hash(nrql: &str) -> String {
+    let hash = nrql.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// `log_ctx!(warn, "...")` logs like `log::warn!` with the request context attached.
+#[macro_export]
+macro_rules! log_ctx {
+    ($level:ident, $($arg:tt)+) => {
+        $crate::log::context::with_mdc(|| ::log::$level!($($arg)+))
+    };
+}
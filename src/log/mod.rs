@@ -1,14 +1,110 @@
-use {env, figlet_rs::FIGfont, log::info};
+pub mod context;
 
-pub fn log_init(path: &str) {
-    log4rs::init_file(path, Default::default()).unwrap();
-    print_banner();
+use {
+    env,
+    figlet_rs::FIGfont,
+    log::{info, warn, LevelFilter},
+    log4rs::{
+        append::console::ConsoleAppender,
+        config::{Appender, Config, Root},
+        encode::{json::JsonEncoder, pattern::PatternEncoder, Encode},
+    },
+    std::{path::Path, str::FromStr},
+};
+
+const DEFAULT_PATTERN: &str =
+    "{h({d(%Y-%m-%d %H:%M:%S)})} | {h({l})} | {M} | {X(request_id)(-)} | {m}{n}";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown log format {}, expected text or json", s)),
+        }
+    }
+}
+
+/// Sets up logging from the log4rs file at `path`, falling back to a built-in
+/// console config when the file is missing or broken. `format` forces the
+/// built-in config in that format, `level` overrides the root level of
+/// whichever config ends up in use.
+pub fn log_init(path: &str, format: Option<LogFormat>, level: Option<LevelFilter>, banner: bool) {
+    let mut fallback_reason = None;
+    let file_config = match format {
+        Some(_) => None,
+        None if !Path::new(path).exists() => {
+            fallback_reason = Some(format!("log config {} not found", path));
+            None
+        }
+        None => match log4rs::config::load_config_file(path, Default::default()) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                fallback_reason = Some(format!("could not load log config {}: {}", path, e));
+                None
+            }
+        },
+    };
+    let format = format.unwrap_or(LogFormat::Text);
+    let mut refreshing = false;
+    let init = match (file_config, level) {
+        // No override: let log4rs keep watching the file for changes.
+        (Some(_), None) => {
+            refreshing = true;
+            log4rs::init_file(path, Default::default()).map(|_| ())
+        }
+        (Some(mut config), Some(level)) => {
+            config.root_mut().set_level(level);
+            log4rs::init_config(config).map(|_| ()).map_err(Into::into)
+        }
+        (None, level) => {
+            log4rs::init_config(default_config(format, level.unwrap_or(LevelFilter::Info)))
+                .map(|_| ())
+                .map_err(Into::into)
+        }
+    };
+    if let Err(e) = init {
+        eprintln!("Could not initialize logging: {}", e);
+    }
+    if banner && format == LogFormat::Text {
+        print_banner();
+    }
+    info!("Starting Enma version: {}", env!("CARGO_PKG_VERSION"));
+    if let Some(reason) = fallback_reason {
+        warn!("{}, using the built-in logging config", reason);
+    } else if !refreshing && level.is_some() {
+        info!(
+            "--log.level is set, {} will not be reloaded on change",
+            path
+        );
+    }
+}
+
+fn default_config(format: LogFormat, level: LevelFilter) -> Config {
+    let encoder: Box<dyn Encode> = match format {
+        LogFormat::Text => Box::new(PatternEncoder::new(DEFAULT_PATTERN)),
+        LogFormat::Json => Box::new(JsonEncoder::new()),
+    };
+    let stdout = ConsoleAppender::builder().encoder(encoder).build();
+    Config::builder()
+        .appender(Appender::builder().build("stdout", Box::new(stdout)))
+        .build(Root::builder().appender("stdout").build(level))
+        .expect("built-in logging config is valid")
 }
 
+/// Goes to stderr so a log4rs file with a JSON encoder on stdout stays parseable.
 fn print_banner() {
-    let standard_font = FIGfont::standand().unwrap();
-    let figure = standard_font.convert("Enma");
-    assert!(figure.is_some());
-    println!("{}", figure.unwrap());
-    info!("Starting Enma version: {}", env!("CARGO_PKG_VERSION"))
+    if let Ok(font) = FIGfont::standand() {
+        if let Some(figure) = font.convert("Enma") {
+            eprintln!("{}", figure);
+        }
+    }
 }
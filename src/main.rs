@@ -1,11 +1,46 @@
-use enma::{cli, config::Config, log, startup::Application};
+use enma::{
+    cli::{self, command, Command},
+    config::Config,
+    log,
+    startup::Application,
+    telemetry::trace,
+};
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let cli = cli::Options::new();
-    log::log_init(cli.get_log_config_path());
-    let config = Config::new(cli.get_config_path());
-    let app = Application::build(config).await?;
+    let config_path = cli.get_config_path();
+    let result = match cli.get_command() {
+        Command::Serve => return serve(&cli).await,
+        Command::CheckConfig => command::check_config(config_path),
+        Command::Query(options) => command::query(config_path, options).await,
+        Command::Nrql { query, output } => command::nrql(config_path, query, *output).await,
+        Command::ListMetrics { output } => command::list_metrics(*output),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+async fn serve(cli: &cli::Options) -> std::io::Result<()> {
+    log::log_init(
+        cli.get_log_config_path(),
+        cli.get_log_format(),
+        cli.get_log_level(),
+        cli.show_banner(),
+    );
+    let config = Config::new(cli.get_config_path()).unwrap_or_else(|e| {
+        ::log::error!("{}", e);
+        std::process::exit(1);
+    });
+    let tracer_provider = trace::init(&config.telemetry);
+    let app = Application::build(config, cli.get_config_path()).await?;
     app.run_until_stopped().await?;
+    if let Some(provider) = tracer_provider {
+        ::log::info!("Flushing pending spans");
+        trace::shutdown(provider);
+    }
     Ok(())
 }
@@ -0,0 +1,46 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum NewrelicError {
+    /// The call to New Relic failed, it answered with a 5xx or 429, or its body could not be read.
+    Request(reqwest::Error),
+    /// New Relic answered with JSON enma does not understand.
+    Decode(serde_json::Error),
+}
+
+impl NewrelicError {
+    /// Error class used as a metrics label.
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::Request(e) if e.is_timeout() => "timeout",
+            Self::Request(e) if e.is_connect() => "connect",
+            Self::Request(e) if e.is_decode() => "decode",
+            Self::Request(e) if e.is_status() => "status",
+            Self::Request(_) => "request",
+            Self::Decode(_) => "decode",
+        }
+    }
+}
+
+impl fmt::Display for NewrelicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "New Relic request failed: {}", e),
+            Self::Decode(e) => write!(f, "could not decode New Relic response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for NewrelicError {}
+
+impl From<reqwest::Error> for NewrelicError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Request(e)
+    }
+}
+
+impl From<serde_json::Error> for NewrelicError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Decode(e)
+    }
+}
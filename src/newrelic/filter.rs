@@ -0,0 +1,73 @@
+use {
+    crate::newrelic::nrql,
+    serde::{Deserialize, Serialize},
+    std::collections::BTreeMap,
+};
+
+/// Narrows the Kubernetes metrics beyond the app label, so apps sharing a name
+/// across namespaces or clusters aren't merged.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct K8sFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster_name: Option<String>,
+    /// Extra pod labels that must match, e.g. `{"team": "payments"}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Who a metric is about: the attribute identifying the application, the value
+/// it must have, and for Kubernetes metrics the namespace, cluster and labels.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub attribute: String,
+    pub application_name: String,
+    pub k8s: K8sFilter,
+}
+
+/// Where the Kubernetes attributes live for an event type.
+pub struct K8sAttributes {
+    pub namespace: &'static str,
+    pub cluster_name: &'static str,
+    /// Prefix of pod label attributes, e.g. `label.` for `label.app`
+    pub label_prefix: &'static str,
+}
+
+impl K8sFilter {
+    pub fn validate(&self) -> Result<(), String> {
+        for key in self.labels.keys() {
+            if key.trim().is_empty() || key.contains('`') {
+                return Err(format!("invalid label name '{}'", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the filter as ` AND ...` conditions, empty when nothing is set.
+    pub fn to_nrql(&self, attributes: &K8sAttributes) -> String {
+        let mut conditions = Vec::new();
+        if let Some(namespace) = self.namespace.as_deref().filter(|s| !s.is_empty()) {
+            conditions.push(format!(
+                "{} = '{}'",
+                attributes.namespace,
+                nrql::escape(namespace)
+            ));
+        }
+        if let Some(cluster) = self.cluster_name.as_deref().filter(|s| !s.is_empty()) {
+            conditions.push(format!(
+                "{} = '{}'",
+                attributes.cluster_name,
+                nrql::escape(cluster)
+            ));
+        }
+        for (key, value) in &self.labels {
+            conditions.push(format!(
+                "{} = '{}'",
+                nrql::attribute(&format!("{}{}", attributes.label_prefix, key)),
+                nrql::escape(value)
+            ));
+        }
+        conditions.iter().map(|c| format!(" AND {}", c)).collect()
+    }
+}
@@ -1,3 +1,12 @@
+use {
+    crate::newrelic::{
+        filter::{K8sAttributes, Target},
+        model::NewrelicResponseModel,
+        nrql,
+    },
+    std::str::FromStr,
+};
+
 #[derive(Debug, Clone, Copy)]
 pub enum Metric {
     CpuUsedCore,
@@ -7,32 +16,377 @@ pub enum Metric {
     Throughput,
     MemoryHeapUsed,
     ThreadCount,
+    /// Web transaction duration at the given percentile, in milliseconds
+    ResponseTimePercentile(f32),
+    /// Percentage of web transactions that errored
+    ErrorRate,
+    /// Apdex score for the given T threshold, in seconds
+    Apdex(f32),
+    /// Milliseconds per minute the JVM spent in garbage collection
+    GcPauseTime,
+    /// Garbage collections per minute
+    GcFrequency,
+    MemoryHeapCommitted,
+    MemoryHeapMax,
+    /// Non-heap memory used by class metadata
+    MemoryMetaspaceUsed,
+    LoadedClassCount,
+    /// Heap used as a percentage of heap max
+    MemoryHeapUtilization,
+    /// Container memory working set, in bytes
+    MemoryWorkingSet,
+    MemoryRequested,
+    MemoryLimit,
+    /// Memory working set as a percentage of the memory request
+    MemoryUtilizationRequest,
+    /// Memory working set as a percentage of the memory limit
+    MemoryUtilizationLimit,
+    CpuLimitCore,
+    /// Percentage of CFS periods in which the container was throttled
+    CpuThrottlingRatio,
+    /// CPU used as a percentage of the CPU request
+    CpuUtilizationRequest,
+    /// CPU used as a percentage of the CPU limit
+    CpuUtilizationLimit,
+    /// Container restarts within the window
+    ContainerRestarts,
+    /// Containers whose last termination was an OOM kill
+    OomKilled,
+}
+
+/// Percentile used by /response-time-percentile when the request doesn't set one.
+pub const DEFAULT_PERCENTILE: f32 = 95.0;
+
+/// NRQL's percentile() only takes values strictly between 0 and 100.
+pub fn is_valid_percentile(p: f32) -> bool {
+    p > 0.0 && p < 100.0
 }
 
+/// Apdex T used when the request doesn't set one, New Relic's own default.
+pub const DEFAULT_APDEX_T: f32 = 0.5;
+
 impl Metric {
-    pub fn get_query(&self, application_name: &str, start_time: &str, end_time: &str) -> String {
+    pub const ALL: &'static [Metric] = &[
+        Self::CpuUsedCore,
+        Self::CpuRquestedCore,
+        Self::TotalPods,
+        Self::MemoryWorkingSet,
+        Self::MemoryRequested,
+        Self::MemoryLimit,
+        Self::MemoryUtilizationRequest,
+        Self::MemoryUtilizationLimit,
+        Self::CpuLimitCore,
+        Self::CpuThrottlingRatio,
+        Self::CpuUtilizationRequest,
+        Self::CpuUtilizationLimit,
+        Self::ContainerRestarts,
+        Self::OomKilled,
+        Self::ResponseTimeAverage,
+        Self::ResponseTimePercentile(50.0),
+        Self::ResponseTimePercentile(90.0),
+        Self::ResponseTimePercentile(95.0),
+        Self::ResponseTimePercentile(99.0),
+        Self::Throughput,
+        Self::ErrorRate,
+        Self::Apdex(DEFAULT_APDEX_T),
+        Self::MemoryHeapUsed,
+        Self::MemoryHeapCommitted,
+        Self::MemoryHeapMax,
+        Self::MemoryHeapUtilization,
+        Self::MemoryMetaspaceUsed,
+        Self::GcPauseTime,
+        Self::GcFrequency,
+        Self::LoadedClassCount,
+        Self::ThreadCount,
+    ];
+
+    /// Path of the metric under /newrelic/v1, also accepted on the command line.
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Self::CpuUsedCore => "cpu-used-core",
+            Self::CpuRquestedCore => "cpu-requested-core",
+            Self::TotalPods => "pods-total",
+            Self::ResponseTimeAverage => "response-time-average",
+            Self::Throughput => "throughput",
+            Self::MemoryHeapUsed => "memory-heap-used",
+            Self::ThreadCount => "thread-count",
+            Self::ResponseTimePercentile(p) => match p.to_string().as_str() {
+                "50" => "response-time-p50",
+                "90" => "response-time-p90",
+                "95" => "response-time-p95",
+                "99" => "response-time-p99",
+                _ => "response-time-percentile",
+            },
+            Self::ErrorRate => "error-rate",
+            Self::Apdex(_) => "apdex",
+            Self::GcPauseTime => "gc-pause-time",
+            Self::GcFrequency => "gc-frequency",
+            Self::MemoryHeapCommitted => "memory-heap-committed",
+            Self::MemoryHeapMax => "memory-heap-max",
+            Self::MemoryMetaspaceUsed => "memory-metaspace-used",
+            Self::LoadedClassCount => "loaded-class-count",
+            Self::MemoryHeapUtilization => "memory-heap-utilization",
+            Self::MemoryWorkingSet => "memory-working-set",
+            Self::MemoryRequested => "memory-requested",
+            Self::MemoryLimit => "memory-limit",
+            Self::MemoryUtilizationRequest => "memory-utilization-request",
+            Self::MemoryUtilizationLimit => "memory-utilization-limit",
+            Self::CpuLimitCore => "cpu-limit-core",
+            Self::CpuThrottlingRatio => "cpu-throttling-ratio",
+            Self::CpuUtilizationRequest => "cpu-utilization-request",
+            Self::CpuUtilizationLimit => "cpu-utilization-limit",
+            Self::ContainerRestarts => "container-restarts",
+            Self::OomKilled => "oom-killed",
+        }
+    }
+
+    pub fn from_slug(slug: &str) -> Option<Self> {
+        if slug == "response-time-percentile" {
+            return Some(Self::ResponseTimePercentile(DEFAULT_PERCENTILE));
+        }
+        Self::ALL.iter().copied().find(|m| m.slug() == slug)
+    }
+
+    /// Looks a metric up by its path or its snake_case name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::from_slug(name).or_else(|| Self::ALL.iter().copied().find(|m| m.name() == name))
+    }
+
+    /// Stable snake_case name, used as a label and in logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CpuUsedCore => "cpu_used_core",
+            Self::CpuRquestedCore => "cpu_requested_core",
+            Self::TotalPods => "total_pods",
+            Self::ResponseTimeAverage => "response_time_average",
+            Self::Throughput => "throughput",
+            Self::MemoryHeapUsed => "memory_heap_used",
+            Self::ThreadCount => "thread_count",
+            Self::ResponseTimePercentile(p) => match p.to_string().as_str() {
+                "50" => "response_time_p50",
+                "90" => "response_time_p90",
+                "95" => "response_time_p95",
+                "99" => "response_time_p99",
+                _ => "response_time_percentile",
+            },
+            Self::ErrorRate => "error_rate",
+            Self::Apdex(_) => "apdex",
+            Self::GcPauseTime => "gc_pause_time",
+            Self::GcFrequency => "gc_frequency",
+            Self::MemoryHeapCommitted => "memory_heap_committed",
+            Self::MemoryHeapMax => "memory_heap_max",
+            Self::MemoryMetaspaceUsed => "memory_metaspace_used",
+            Self::LoadedClassCount => "loaded_class_count",
+            Self::MemoryHeapUtilization => "memory_heap_utilization",
+            Self::MemoryWorkingSet => "memory_working_set",
+            Self::MemoryRequested => "memory_requested",
+            Self::MemoryLimit => "memory_limit",
+            Self::MemoryUtilizationRequest => "memory_utilization_request",
+            Self::MemoryUtilizationLimit => "memory_utilization_limit",
+            Self::CpuLimitCore => "cpu_limit_core",
+            Self::CpuThrottlingRatio => "cpu_throttling_ratio",
+            Self::CpuUtilizationRequest => "cpu_utilization_request",
+            Self::CpuUtilizationLimit => "cpu_utilization_limit",
+            Self::ContainerRestarts => "container_restarts",
+            Self::OomKilled => "oom_killed",
+        }
+    }
+
+    /// Where this metric's value sits in a New Relic result entry, see
+    /// `NewrelicResultModel::get_value`.
+    pub fn result_path(&self) -> &'static [&'static str] {
+        match self {
+            Self::CpuUsedCore
+            | Self::CpuRquestedCore
+            | Self::MemoryHeapUsed
+            | Self::MemoryHeapCommitted
+            | Self::MemoryHeapMax
+            | Self::MemoryMetaspaceUsed
+            | Self::LoadedClassCount
+            | Self::ThreadCount
+            | Self::MemoryWorkingSet
+            | Self::MemoryRequested
+            | Self::MemoryLimit
+            | Self::CpuLimitCore => &["average"],
+            Self::ResponseTimeAverage
+            | Self::Throughput
+            | Self::ErrorRate
+            | Self::GcPauseTime
+            | Self::GcFrequency => &["result"],
+            Self::MemoryHeapUtilization => &["heapUtilization"],
+            Self::TotalPods => &["uniqueCount"],
+            Self::ContainerRestarts | Self::OomKilled => &["sum"],
+            Self::MemoryUtilizationRequest
+            | Self::MemoryUtilizationLimit
+            | Self::CpuUtilizationRequest
+            | Self::CpuUtilizationLimit => &["utilization"],
+            Self::CpuThrottlingRatio => &["throttled"],
+            // {"percentile": {"95": 0.42}}, unwrapped by get_value
+            Self::ResponseTimePercentile(_) => &["percentile"],
+            // the full breakdown is read with NewrelicResponseModel::get_apdex
+            Self::Apdex(_) => &["score"],
+        }
+    }
+
+    /// Factor applied to the extracted value, e.g. seconds to milliseconds.
+    fn scale(&self) -> f32 {
+        match self {
+            Self::ResponseTimePercentile(_) => 1000.0,
+            _ => 1.0,
+        }
+    }
+
+    /// Reads this metric's value out of a New Relic response.
+    pub fn extract(&self, res: &NewrelicResponseModel) -> Option<f32> {
+        res.get_value(self.result_path()).map(|v| v * self.scale())
+    }
+
+    /// Whether a zero from New Relic means there was no data rather than a real zero.
+    pub fn zero_is_missing(&self) -> bool {
+        matches!(self, Self::Throughput | Self::TotalPods)
+    }
+
+    /// Where the namespace, cluster and labels live for Kubernetes metrics, None for the rest.
+    pub fn k8s_attributes(&self) -> Option<K8sAttributes> {
+        match self {
+            Self::CpuUsedCore | Self::CpuRquestedCore => Some(K8sAttributes {
+                namespace: "tags.namespaceName",
+                cluster_name: "tags.clusterName",
+                label_prefix: "tags.",
+            }),
+            Self::TotalPods
+            | Self::MemoryWorkingSet
+            | Self::MemoryRequested
+            | Self::MemoryLimit
+            | Self::MemoryUtilizationRequest
+            | Self::MemoryUtilizationLimit
+            | Self::CpuLimitCore
+            | Self::CpuThrottlingRatio
+            | Self::CpuUtilizationRequest
+            | Self::CpuUtilizationLimit
+            | Self::ContainerRestarts
+            | Self::OomKilled => Some(K8sAttributes {
+                namespace: "namespaceName",
+                cluster_name: "clusterName",
+                label_prefix: "label.",
+            }),
+            _ => None,
+        }
+    }
+
+    /// Renders the metric's NRQL for `target`. The Kubernetes filter only applies to
+    /// Kubernetes metrics.
+    pub fn get_query(&self, target: &Target, start_time: &str, end_time: &str) -> String {
+        let attribute = nrql::attribute(&target.attribute);
+        let application_name = nrql::escape(&target.application_name);
+        let k8s_filter = self
+            .k8s_attributes()
+            .map(|attributes| target.k8s.to_nrql(&attributes))
+            .unwrap_or_default();
         match self {
             Self::CpuRquestedCore => {
-                format!("from Metric SELECT average(k8s.container.cpuRequestedCores) where tags.app = '{}' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("from Metric SELECT average(k8s.container.cpuRequestedCores) where {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
             }
             Self::CpuUsedCore => {
-                format!("from Metric SELECT average(k8s.container.cpuUsedCores) where tags.app = '{}' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("from Metric SELECT average(k8s.container.cpuUsedCores) where {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
             }
             Self::MemoryHeapUsed => {
-                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE appName = '{}' AND metricTimesliceName = 'Memory/Heap/Used' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE {} = '{}' AND metricTimesliceName = 'Memory/Heap/Used' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
             }
             Self::ResponseTimeAverage => {
-                format!("SELECT average(duration) * 1000 FROM Transaction WHERE appName = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("SELECT average(duration) * 1000 FROM Transaction WHERE {} = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
             }
             Self::ThreadCount => {
-                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE appName = '{}' AND metricTimesliceName = 'JmxBuiltIn/Threads/Thread Count' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE {} = '{}' AND metricTimesliceName = 'JmxBuiltIn/Threads/Thread Count' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
             }
             Self::Throughput => {
-                format!("SELECT rate(count(apm.service.transaction.duration), 1 minute) FROM Metric, Transaction WHERE appName = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("SELECT rate(count(apm.service.transaction.duration), 1 minute) FROM Metric, Transaction WHERE {} = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::ResponseTimePercentile(p) => {
+                format!("SELECT percentile(duration, {}) FROM Transaction WHERE {} = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", p, attribute, application_name, start_time, end_time)
+            }
+            Self::ErrorRate => {
+                format!("SELECT percentage(count(*), WHERE error IS true) FROM Transaction WHERE {} = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::Apdex(t) => {
+                format!("SELECT apdex(duration, t: {}) FROM Transaction WHERE {} = '{}' AND transactionType = 'Web' SINCE {} UNTIL {}", t, attribute, application_name, start_time, end_time)
+            }
+            Self::MemoryHeapCommitted => {
+                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE {} = '{}' AND metricTimesliceName = 'Memory/Heap/Committed' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::MemoryHeapMax => {
+                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE {} = '{}' AND metricTimesliceName = 'Memory/Heap/Max' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::MemoryHeapUtilization => {
+                format!("SELECT filter(average(newrelic.timeslice.value), WHERE metricTimesliceName = 'Memory/Heap/Used') / filter(average(newrelic.timeslice.value), WHERE metricTimesliceName = 'Memory/Heap/Max') * 100 AS 'heapUtilization' FROM Metric WHERE {} = '{}' AND metricTimesliceName IN ('Memory/Heap/Used', 'Memory/Heap/Max') SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::MemoryMetaspaceUsed => {
+                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE {} = '{}' AND metricTimesliceName = 'MemoryPool/Non-Heap/Metaspace/Used' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::LoadedClassCount => {
+                format!("SELECT average(newrelic.timeslice.value) FROM Metric WHERE {} = '{}' AND metricTimesliceName = 'JmxBuiltIn/Classes/Loaded' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::GcPauseTime => {
+                format!("SELECT rate(sum(newrelic.timeslice.value), 1 minute) * 1000 FROM Metric WHERE {} = '{}' AND metricTimesliceName LIKE 'GC/%' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::GcFrequency => {
+                format!("SELECT rate(count(newrelic.timeslice.value), 1 minute) FROM Metric WHERE {} = '{}' AND metricTimesliceName LIKE 'GC/%' SINCE {} UNTIL {}", attribute, application_name, start_time, end_time)
+            }
+            Self::MemoryWorkingSet => {
+                format!("FROM K8sContainerSample SELECT average(memoryWorkingSetBytes) WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::MemoryRequested => {
+                format!("FROM K8sContainerSample SELECT average(memoryRequestedBytes) WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::MemoryLimit => {
+                format!("FROM K8sContainerSample SELECT average(memoryLimitBytes) WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::MemoryUtilizationRequest => {
+                format!("FROM K8sContainerSample SELECT average(memoryWorkingSetBytes) / average(memoryRequestedBytes) * 100 AS 'utilization' WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::MemoryUtilizationLimit => {
+                format!("FROM K8sContainerSample SELECT average(memoryWorkingSetBytes) / average(memoryLimitBytes) * 100 AS 'utilization' WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::CpuLimitCore => {
+                format!("FROM K8sContainerSample SELECT average(cpuLimitCores) WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::CpuThrottlingRatio => {
+                format!("FROM K8sContainerSample SELECT sum(containerCpuCfsThrottledPeriodsDelta) / sum(containerCpuCfsPeriodsDelta) * 100 AS 'throttled' WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::CpuUtilizationRequest => {
+                format!("FROM K8sContainerSample SELECT average(cpuUsedCores) / average(cpuRequestedCores) * 100 AS 'utilization' WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::CpuUtilizationLimit => {
+                format!("FROM K8sContainerSample SELECT average(cpuUsedCores) / average(cpuLimitCores) * 100 AS 'utilization' WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::ContainerRestarts => {
+                format!("FROM K8sContainerSample SELECT sum(restartCountDelta) WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
+            }
+            Self::OomKilled => {
+                // only samples that restarted within the window, so older OOM kills aren't counted
+                format!("FROM K8sContainerSample SELECT sum(restartCountDelta) WHERE {} = '{}' AND lastTerminatedExitReason = 'OOMKilled' AND restartCountDelta > 0{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
             }
             Self::TotalPods => {
-                format!("FROM K8sContainerSample SELECT uniqueCount(podName) WHERE label.app = '{}' SINCE {} UNTIL {}", application_name, start_time, end_time)
+                format!("FROM K8sContainerSample SELECT uniqueCount(podName) WHERE {} = '{}'{} SINCE {} UNTIL {}", attribute, application_name, k8s_filter, start_time, end_time)
             }
         }
     }
 }
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_slug(s).ok_or_else(|| {
+            format!(
+                "unknown metric {}, expected one of: {}",
+                s,
+                Self::ALL
+                    .iter()
+                    .map(|m| m.slug())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })
+    }
+}
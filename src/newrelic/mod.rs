@@ -1,3 +1,8 @@
+pub mod error;
+pub mod filter;
 pub mod metric;
 pub mod model;
+#[allow(clippy::module_inception)]
 pub mod newrelic;
+pub mod nrql;
+pub mod window;
@@ -1,36 +1,180 @@
-use serde::Deserialize;
+use {
+    serde::{Deserialize, Serialize},
+    serde_json::{Map, Value},
+};
 
+/// One entry of New Relic's `results`, keyed by aggregate function name
+/// (`average`, `percentile`, `latest`, `uniqueCount`, ...) or by alias.
 #[derive(Deserialize, Debug)]
-pub struct NewrelicResultModel {
-    pub result: Option<f32>,
-    pub average: Option<f32>,
-    #[serde(rename(deserialize = "uniqueCount"))]
-    pub unique_count: Option<f32>,
+#[serde(transparent)]
+pub struct NewrelicResultModel(Map<String, Value>);
+
+impl NewrelicResultModel {
+    /// Follows `path` (e.g. `["percentile", "95"]`) to a number. An object with
+    /// a single entry at the end of the path is unwrapped, so `["percentile"]`
+    /// also reads `{"percentile": {"95": 0.4}}`.
+    pub fn get_value(&self, path: &[&str]) -> Option<f32> {
+        let (first, rest) = path.split_first()?;
+        let mut value = self.0.get(*first)?;
+        for key in rest {
+            value = value.get(*key)?;
+        }
+        if let Value::Object(map) = value {
+            if map.len() == 1 {
+                value = map.values().next()?;
+            }
+        }
+        value.as_f64().map(|v| v as f32)
+    }
+
+    /// Reads the entry as an apdex() result, if it is one.
+    pub fn get_apdex(&self) -> Option<NewrelicApdexModel> {
+        if !self.0.contains_key("score") {
+            return None;
+        }
+        serde_json::from_value(Value::Object(self.0.clone())).ok()
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// Result of NRQL's apdex(): the score and how many transactions fell in each bucket.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewrelicApdexModel {
+    pub score: Option<f32>,
+    #[serde(rename(deserialize = "s"), default)]
+    pub satisfied: u64,
+    #[serde(rename(deserialize = "t"), default)]
+    pub tolerating: u64,
+    #[serde(rename(deserialize = "f"), default)]
+    pub frustrated: u64,
+    #[serde(default)]
+    pub count: u64,
+}
+
+/// The parts of New Relic's `metadata` enma reports back: the window it
+/// actually evaluated and any messages (e.g. that the query was limited).
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
 pub struct NewrelicMetadataModel {
     messages: Vec<String>,
+    begin_time: Option<String>,
+    end_time: Option<String>,
+}
+
+impl NewrelicMetadataModel {
+    pub fn get_begin_time(&self) -> Option<&str> {
+        self.begin_time.as_deref()
+    }
+
+    pub fn get_end_time(&self) -> Option<&str> {
+        self.end_time.as_deref()
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NewrelicPerformanceStatsModel {
+    inspected_count: Option<u64>,
+    omitted_count: Option<u64>,
+    match_count: Option<u64>,
+    /// Milliseconds New Relic spent on the query
+    wall_clock_time: Option<u64>,
+}
+
+impl NewrelicPerformanceStatsModel {
+    pub fn get_inspected_count(&self) -> Option<u64> {
+        self.inspected_count
+    }
+
+    pub fn get_omitted_count(&self) -> Option<u64> {
+        self.omitted_count
+    }
+
+    pub fn get_match_count(&self) -> Option<u64> {
+        self.match_count
+    }
+
+    pub fn get_wall_clock_time(&self) -> Option<u64> {
+        self.wall_clock_time
+    }
 }
 
 #[derive(Deserialize, Debug)]
 pub struct NewrelicResponseModel {
     results: Vec<NewrelicResultModel>,
+    #[serde(default)]
     metadata: NewrelicMetadataModel,
+    #[serde(default, rename(deserialize = "performanceStats"))]
+    performance_stats: NewrelicPerformanceStatsModel,
 }
 
 impl NewrelicResponseModel {
-    pub fn get_average(&self) -> Option<f32> {
-        self.results[0].average
+    /// First number found at `path` across the result entries; queries selecting
+    /// several aggregates get one entry per aggregate.
+    pub fn get_value(&self, path: &[&str]) -> Option<f32> {
+        self.results.iter().find_map(|r| r.get_value(path))
     }
 
-    pub fn get_unique_count(&self) -> Option<f32> {
-        self.results[0].unique_count
+    pub fn get_apdex(&self) -> Option<NewrelicApdexModel> {
+        self.results.iter().find_map(|r| r.get_apdex())
     }
 
-    pub fn get_result(&self) -> Option<f32> {
-        self.results[0].result
+    pub fn get_metadata(&self) -> &NewrelicMetadataModel {
+        &self.metadata
+    }
+
+    pub fn get_performance_stats(&self) -> &NewrelicPerformanceStatsModel {
+        &self.performance_stats
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        warnings(&self.metadata, &self.performance_stats)
+    }
+}
+
+/// Payload of a `COMPARE WITH` query: the results for the requested window and
+/// for the window it is compared with.
+#[derive(Deserialize, Debug)]
+pub struct NewrelicCompareResponseModel {
+    current: NewrelicResponseModel,
+    previous: NewrelicResponseModel,
+    #[serde(default)]
+    metadata: NewrelicMetadataModel,
+    #[serde(default, rename(deserialize = "performanceStats"))]
+    performance_stats: NewrelicPerformanceStatsModel,
+}
+
+impl NewrelicCompareResponseModel {
+    pub fn get_current(&self) -> &NewrelicResponseModel {
+        &self.current
+    }
+
+    pub fn get_previous(&self) -> &NewrelicResponseModel {
+        &self.previous
+    }
+
+    pub fn get_metadata(&self) -> &NewrelicMetadataModel {
+        &self.metadata
+    }
+
+    pub fn get_performance_stats(&self) -> &NewrelicPerformanceStatsModel {
+        &self.performance_stats
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        warnings(&self.metadata, &self.performance_stats)
+    }
+}
+
+/// New Relic's own messages plus a note when events were left out of the result.
+fn warnings(
+    metadata: &NewrelicMetadataModel,
+    stats: &NewrelicPerformanceStatsModel,
+) -> Vec<String> {
+    let mut warnings = metadata.messages.clone();
+    if let Some(omitted) = stats.omitted_count.filter(|c| *c > 0) {
+        warnings.push(format!("{} events were omitted from the result", omitted));
     }
+    warnings
 }
 
 #[derive(Deserialize, Debug)]
@@ -41,7 +185,7 @@ pub struct NewRelicErrorResponseModel {
 
 impl NewRelicErrorResponseModel {
     pub fn get_error_msg(&self) -> &str {
-        return self.error_msg.as_str();
+        self.error_msg.as_str()
     }
 }
 
@@ -49,5 +193,6 @@ impl NewRelicErrorResponseModel {
 #[serde(untagged)]
 pub enum NewrelicQueryResult {
     Ok(NewrelicResponseModel),
+    Compare(Box<NewrelicCompareResponseModel>),
     Err(NewRelicErrorResponseModel),
 }
@@ -1,42 +1,118 @@
-use crate::{
-    config::NewrelicConfig, newrelic::metric::Metric, newrelic::model::NewrelicQueryResult,
+use {
+    crate::{
+        config::NewrelicConfig,
+        log::context,
+        log_ctx,
+        newrelic::metric::Metric,
+        newrelic::{error::NewrelicError, model::NewrelicQueryResult},
+        telemetry::metrics::{UPSTREAM_DURATION, UPSTREAM_ERRORS, UPSTREAM_IN_FLIGHT},
+    },
+    reqwest::StatusCode,
+    serde_json::Value,
+    std::time::Instant,
+    tracing::{field, instrument, Span},
 };
 
 #[derive(Clone)]
 pub struct Newrelic {
     api_key: String,
-    account_id: i32,
+    account_id: u32,
+    endpoint: String,
     http_client: reqwest::Client,
 }
 
 impl Newrelic {
     pub fn new(newrelic_config: &NewrelicConfig) -> Self {
         let client = reqwest::Client::new();
-        return Self {
+        Self {
             http_client: client,
             api_key: newrelic_config.get_api_key().to_string(),
             account_id: newrelic_config.get_account_id(),
-        };
+            endpoint: newrelic_config.get_endpoint().to_string(),
+        }
     }
+
+    pub fn get_account_id(&self) -> u32 {
+        self.account_id
+    }
+
+    pub fn get_endpoint(&self) -> &str {
+        self.endpoint.as_str()
+    }
+
+    /// Runs `nrql`, as rendered by `Metric::get_query`, and decodes the result.
     pub async fn go_query(
         &self,
-        application_name: &str,
-        start_time: &str,
-        end_time: &str,
         metric: Metric,
-    ) -> Result<NewrelicQueryResult, reqwest::Error> {
-        let query = metric.get_query(application_name, start_time, end_time);
-        let full_url = format!(
-            "https://insights-api.newrelic.com/v1/accounts/{}/query?nrql={}",
-            self.account_id, query
-        );
+        nrql: &str,
+    ) -> Result<NewrelicQueryResult, NewrelicError> {
+        let raw = self.run_nrql(nrql, metric.name()).await?;
+        serde_json::from_value(raw).map_err(|e| {
+            UPSTREAM_ERRORS
+                .with_label_values(&[metric.name(), "decode"])
+                .inc();
+            e.into()
+        })
+    }
+
+    /// Runs `nrql` and returns New Relic's JSON as is. `label` names the query
+    /// in metrics, traces and logs (the metric name, or "nrql" for raw queries).
+    #[instrument(
+        name = "newrelic.query",
+        skip(self, nrql),
+        fields(
+            otel.kind = "client",
+            metric = label,
+            newrelic.account_id = self.account_id,
+            nrql = nrql,
+            error = field::Empty,
+        )
+    )]
+    pub async fn run_nrql(&self, nrql: &str, label: &str) -> Result<Value, NewrelicError> {
+        context::update(|c| c.nrql_hash = Some(context::nrql_hash(nrql)));
+        let in_flight = UPSTREAM_IN_FLIGHT.with_label_values(&[label]);
+        in_flight.inc();
+        let timer = UPSTREAM_DURATION.with_label_values(&[label]).start_timer();
+        let result = self.send(nrql).await;
+        timer.observe_duration();
+        in_flight.dec();
+        match &result {
+            Ok(raw) => {
+                if let Some(msg) = raw.get("error") {
+                    Span::current().record("error", field::display(msg));
+                    UPSTREAM_ERRORS.with_label_values(&[label, "nrql"]).inc()
+                }
+            }
+            Err(e) => {
+                Span::current().record("error", field::display(e));
+                UPSTREAM_ERRORS.with_label_values(&[label, e.class()]).inc()
+            }
+        }
+        result
+    }
+
+    async fn send(&self, nrql: &str) -> Result<Value, NewrelicError> {
+        let full_url = format!("{}/v1/accounts/{}/query", self.endpoint, self.account_id);
+        let started = Instant::now();
         let resp = self
             .http_client
             .get(full_url)
+            .query(&[("nrql", nrql)])
             .header("X-Query-Key", self.api_key.as_str())
             .send()
             .await?;
+        context::update(|c| {
+            c.upstream_status = Some(resp.status().as_u16());
+            c.duration_ms = Some(started.elapsed().as_millis());
+        });
+        log_ctx!(info, "New Relic responded with {}", resp.status());
+        // an outage or rate limit is an upstream failure even when the body is
+        // JSON that would decode as a query error
+        let status = resp.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            resp.error_for_status_ref()?;
+        }
 
-        resp.json::<NewrelicQueryResult>().await
+        Ok(resp.json::<Value>().await?)
     }
 }
@@ -0,0 +1,334 @@
+use std::time::Duration;
+
+/// NRQL clauses that can follow the event type list of a FROM clause.
+const CLAUSE_KEYWORDS: [&str; 14] = [
+    "SELECT",
+    "WHERE",
+    "SINCE",
+    "UNTIL",
+    "FACET",
+    "LIMIT",
+    "TIMESERIES",
+    "COMPARE",
+    "WITH",
+    "EXTRAPOLATE",
+    "ORDER",
+    "OFFSET",
+    "SLIDE",
+    "AS",
+];
+
+/// Splits NRQL into words, keeping quoted strings and punctuation as their own
+/// tokens so keywords inside string literals are never mistaken for clauses.
+/// Backslash escapes inside quotes are honoured, and an unterminated quote is an
+/// error rather than a guess at where the string ends.
+fn tokenize(nrql: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = nrql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                let mut quoted = c.to_string();
+                let mut terminated = false;
+                while let Some(q) = chars.next() {
+                    quoted.push(q);
+                    if q == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            quoted.push(escaped);
+                        }
+                    } else if q == c {
+                        terminated = true;
+                        break;
+                    }
+                }
+                if !terminated {
+                    return Err(format!("unterminated {} quote in query", c));
+                }
+                tokens.push(quoted);
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            ',' | '(' | ')' => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+                tokens.push(c.to_string());
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+/// Escapes a value for use inside a single-quoted NRQL string literal.
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+/// Writes an attribute name for NRQL, backtick-quoting names like
+/// `label.app.kubernetes.io/name` that aren't plain identifiers.
+pub fn attribute(name: &str) -> String {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        name.to_string()
+    } else {
+        format!("`{}`", name)
+    }
+}
+
+fn is_keyword(token: &str, keyword: &str) -> bool {
+    token.eq_ignore_ascii_case(keyword)
+}
+
+/// Whether the query has `keyword` as a clause, outside of string literals.
+/// A query that doesn't tokenize counts as having it.
+pub fn has_clause(nrql: &str, keyword: &str) -> bool {
+    match tokenize(nrql) {
+        Ok(tokens) => tokens.iter().any(|t| is_keyword(t, keyword)),
+        Err(_) => true,
+    }
+}
+
+/// Event types named in every FROM clause of the query, including subqueries.
+pub fn event_types(nrql: &str) -> Result<Vec<String>, String> {
+    let tokens = tokenize(nrql)?;
+    let mut types = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        if !is_keyword(&tokens[i], "FROM") {
+            i += 1;
+            continue;
+        }
+        i += 1;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            if token == "," {
+                i += 1;
+                continue;
+            }
+            if token == "(" || token == ")" || CLAUSE_KEYWORDS.iter().any(|k| is_keyword(token, k))
+            {
+                break;
+            }
+            types.push(token.trim_matches('`').to_string());
+            i += 1;
+        }
+    }
+    Ok(types)
+}
+
+/// Parses relative NRQL time like `5 minutes ago` or `1 hour ago` into how far back it reaches.
+pub fn parse_relative(value: &str) -> Option<Duration> {
+    let words: Vec<&str> = value.split_whitespace().collect();
+    let (amount, unit) = match words.as_slice() {
+        [amount, unit, ago] if ago.eq_ignore_ascii_case("ago") => (*amount, *unit),
+        _ => return None,
+    };
+    let amount: u64 = amount.parse().ok()?;
+    let unit = unit.to_ascii_lowercase();
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        _ => return None,
+    };
+    Some(Duration::from_secs(amount.checked_mul(seconds)?))
+}
+
+/// The words from `tokens[start]` up to the next clause keyword or parenthesis.
+fn clause_at(tokens: &[String], start: usize) -> String {
+    let words: Vec<&str> = tokens[start.min(tokens.len())..]
+        .iter()
+        .take_while(|t| *t != "(" && *t != ")" && !CLAUSE_KEYWORDS.iter().any(|k| is_keyword(t, k)))
+        .map(String::as_str)
+        .collect();
+    words.join(" ")
+}
+
+/// Longest relative time following any `keyword`, None when the query has none.
+fn longest_relative(tokens: &[String], keyword: &str) -> Result<Option<Duration>, String> {
+    let mut longest = None;
+    for (i, _) in tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| is_keyword(t, keyword))
+    {
+        let clause = clause_at(tokens, i + 1);
+        let duration = parse_relative(&clause).ok_or_else(|| {
+            format!(
+                "unsupported {} clause '{}', use relative time like '1 hour ago'",
+                keyword, clause
+            )
+        })?;
+        longest = longest.max(Some(duration));
+    }
+    Ok(longest)
+}
+
+/// How far back the query's SINCE clauses reach, taking the longest when
+/// subqueries have their own. New Relic defaults to one hour without SINCE;
+/// anything other than relative `<n> <unit> ago` is an error.
+pub fn since_window(nrql: &str) -> Result<Duration, String> {
+    let tokens = tokenize(nrql)?;
+    Ok(longest_relative(&tokens, "SINCE")?.unwrap_or_else(|| Duration::from_secs(3600)))
+}
+
+/// How far back the query reads data: the longest SINCE plus the longest
+/// `COMPARE WITH` offset, as the compared window sits that much further back.
+/// UNTIL must be `now` or relative time, so an absolute UNTIL can't move the
+/// window past what SINCE allows.
+pub fn reach(nrql: &str) -> Result<Duration, String> {
+    let tokens = tokenize(nrql)?;
+    for (i, _) in tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| is_keyword(t, "UNTIL"))
+    {
+        let clause = clause_at(&tokens, i + 1);
+        if !clause.eq_ignore_ascii_case("now") && parse_relative(&clause).is_none() {
+            return Err(format!(
+                "unsupported UNTIL clause '{}', use now or relative time like '10 minutes ago'",
+                clause
+            ));
+        }
+    }
+    let mut offset = Duration::from_secs(0);
+    for (i, _) in tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| is_keyword(t, "COMPARE"))
+    {
+        if !matches!(tokens.get(i + 1), Some(t) if is_keyword(t, "WITH")) {
+            return Err(String::from("COMPARE must be followed by WITH"));
+        }
+        let clause = clause_at(&tokens, i + 2);
+        let duration = parse_relative(&clause).ok_or_else(|| {
+            format!(
+                "unsupported COMPARE WITH clause '{}', use relative time like '1 week ago'",
+                clause
+            )
+        })?;
+        offset = offset.max(duration);
+    }
+    let since = longest_relative(&tokens, "SINCE")?.unwrap_or_else(|| Duration::from_secs(3600));
+    Ok(since + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_quote_does_not_end_the_string() {
+        let nrql = r"FROM Transaction SELECT count(*) WHERE name = 'a\'' OR name IN (FROM Log SELECT uniques(message) WHERE x = '') SINCE 30 days ago";
+        assert_eq!(event_types(nrql).unwrap(), vec!["Transaction", "Log"]);
+        assert_eq!(since_window(nrql).unwrap(), Duration::from_secs(30 * 86400));
+    }
+
+    #[test]
+    fn keywords_inside_strings_are_ignored() {
+        let nrql = "FROM Transaction SELECT count(*) WHERE name = 'FROM Log SINCE 9 weeks ago'";
+        assert_eq!(event_types(nrql).unwrap(), vec!["Transaction"]);
+        assert_eq!(since_window(nrql).unwrap(), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn unterminated_quote_is_rejected() {
+        assert!(event_types("FROM Transaction SELECT count(*) WHERE name = 'a\\'").is_err());
+        assert!(since_window("FROM Log SELECT * WHERE x = \"open SINCE 1 hour ago").is_err());
+        assert!(has_clause("FROM Log SELECT * WHERE x = 'open", "SINCE"));
+    }
+
+    #[test]
+    fn subqueries_are_included() {
+        let nrql = "SELECT count(*) FROM Transaction WHERE traceId IN (SELECT traceId FROM Span WHERE error IS true)";
+        assert_eq!(event_types(nrql).unwrap(), vec!["Transaction", "Span"]);
+    }
+
+    #[test]
+    fn comma_separated_from_lists() {
+        let nrql = "SELECT count(*) FROM Transaction, `Log`,PageView SINCE 2 hours ago";
+        assert_eq!(
+            event_types(nrql).unwrap(),
+            vec!["Transaction", "Log", "PageView"]
+        );
+    }
+
+    #[test]
+    fn since_extraction() {
+        assert_eq!(
+            since_window("FROM Transaction SELECT count(*)").unwrap(),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(
+            since_window("FROM Transaction SELECT count(*) since 5 MINUTES AGO until now").unwrap(),
+            Duration::from_secs(300)
+        );
+        assert_eq!(
+            since_window("FROM Transaction SELECT count(*) SINCE 1 day ago LIMIT 10").unwrap(),
+            Duration::from_secs(86400)
+        );
+        assert!(since_window("FROM Transaction SELECT count(*) SINCE 1628000000000").is_err());
+    }
+
+    #[test]
+    fn longest_since_wins_across_subqueries() {
+        let nrql = "FROM Transaction SELECT count(*) WHERE x IN (FROM Log SELECT uniques(x) SINCE 1 hour ago) SINCE 30 days ago";
+        assert_eq!(since_window(nrql).unwrap(), Duration::from_secs(30 * 86400));
+        let nrql = "FROM Transaction SELECT count(*) WHERE x IN (FROM Log SELECT uniques(x) SINCE 30 days ago) SINCE 1 hour ago";
+        assert_eq!(since_window(nrql).unwrap(), Duration::from_secs(30 * 86400));
+    }
+
+    #[test]
+    fn reach_adds_the_compare_with_offset() {
+        let nrql = "SELECT count(*) FROM Transaction SINCE 5 minutes ago COMPARE WITH 52 weeks ago";
+        assert_eq!(since_window(nrql).unwrap(), Duration::from_secs(300));
+        assert_eq!(
+            reach(nrql).unwrap(),
+            Duration::from_secs(300 + 52 * 7 * 86400)
+        );
+        assert_eq!(
+            reach("FROM Transaction SELECT count(*) compare with 1 hour ago").unwrap(),
+            Duration::from_secs(7200)
+        );
+        assert!(reach("FROM Transaction SELECT count(*) SINCE 1 hour ago COMPARE WITH").is_err());
+        assert!(reach("FROM Transaction SELECT count(*) COMPARE 1 week ago").is_err());
+    }
+
+    #[test]
+    fn reach_checks_until() {
+        assert_eq!(
+            reach("FROM Transaction SELECT count(*) SINCE 2 hours ago UNTIL 1 hour ago").unwrap(),
+            Duration::from_secs(7200)
+        );
+        assert_eq!(
+            reach("FROM Transaction SELECT count(*) SINCE 2 hours ago UNTIL NOW").unwrap(),
+            Duration::from_secs(7200)
+        );
+        assert!(
+            reach("FROM Transaction SELECT count(*) SINCE 1 hour ago UNTIL 1500000000000").is_err()
+        );
+    }
+
+    #[test]
+    fn escape_round_trips_through_tokenize() {
+        let value = r"o'brien\";
+        let nrql = format!("FROM Log SELECT * WHERE name = '{}'", escape(value));
+        assert_eq!(event_types(&nrql).unwrap(), vec!["Log"]);
+        assert!(!has_clause(&nrql, "SINCE"));
+    }
+}
@@ -0,0 +1,242 @@
+use {
+    crate::{config::TimeConfig, newrelic::nrql},
+    chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc},
+    chrono_tz::Tz,
+    serde::{de, Deserialize, Deserializer},
+};
+
+/// How far past the server clock an end time may be, to absorb client clock skew.
+const FUTURE_TOLERANCE_SECONDS: i64 = 60;
+
+/// A query window checked against the time rules and rendered as NRQL-safe
+/// SINCE/UNTIL literals.
+#[derive(Debug, Clone)]
+pub struct TimeWindow {
+    pub since: String,
+    pub until: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// One side of the window as the caller sent it.
+enum TimePoint {
+    Now,
+    /// `<n> <unit> ago`
+    Relative(std::time::Duration),
+    Absolute(DateTime<Utc>),
+}
+
+impl TimePoint {
+    /// Accepts `now`, relative NRQL time (`5 minutes ago`), epoch milliseconds,
+    /// RFC 3339, or a local date/time (`2021-08-01 13:00:00`, `2021-08-01`) read in `tz`.
+    fn parse(value: &str, tz: Tz) -> Result<Self, String> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("now") {
+            return Ok(Self::Now);
+        }
+        if let Some(duration) = nrql::parse_relative(value) {
+            return Ok(Self::Relative(duration));
+        }
+        if !value.is_empty() && value.chars().all(|c| c.is_ascii_digit()) {
+            let millis: i64 = value
+                .parse()
+                .map_err(|_| format!("epoch milliseconds {} out of range", value))?;
+            return Utc
+                .timestamp_millis_opt(millis)
+                .single()
+                .map(Self::Absolute)
+                .ok_or_else(|| format!("epoch milliseconds {} out of range", value));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            return Ok(Self::Absolute(time.with_timezone(&Utc)));
+        }
+        let local = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+            .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|d| d.and_hms(0, 0, 0)));
+        if let Ok(local) = local {
+            return tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|time| Self::Absolute(time.with_timezone(&Utc)))
+                .ok_or_else(|| format!("{} does not exist in {}", value, tz.name()));
+        }
+        Err(format!(
+            "unsupported time '{}', use RFC 3339, epoch milliseconds or relative time like '1 hour ago'",
+            value
+        ))
+    }
+
+    fn resolve(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+        match self {
+            Self::Now => Ok(now),
+            Self::Relative(duration) => Duration::from_std(*duration)
+                .ok()
+                .and_then(|d| now.checked_sub_signed(d))
+                .ok_or_else(|| String::from("relative time out of range")),
+            Self::Absolute(time) => Ok(*time),
+        }
+    }
+
+    /// Relative times stay relative, counted in seconds; absolute ones become
+    /// epoch milliseconds.
+    fn to_nrql(&self, resolved: DateTime<Utc>) -> String {
+        match self {
+            Self::Now => String::from("now"),
+            Self::Relative(duration) => format!("{} seconds ago", duration.as_secs()),
+            Self::Absolute(_) => resolved.timestamp_millis().to_string(),
+        }
+    }
+}
+
+impl TimeWindow {
+    pub fn parse(start_time: &str, end_time: &str, config: &TimeConfig) -> Result<Self, String> {
+        let tz = config.get_timezone();
+        let now = Utc::now();
+        let start_point =
+            TimePoint::parse(start_time, tz).map_err(|e| format!("start_time: {}", e))?;
+        let end_point = TimePoint::parse(end_time, tz).map_err(|e| format!("end_time: {}", e))?;
+        let start = start_point.resolve(now)?;
+        let end = end_point.resolve(now)?;
+        if start >= end {
+            return Err(String::from("start_time must be before end_time"));
+        }
+        if end > now + Duration::seconds(FUTURE_TOLERANCE_SECONDS) {
+            return Err(String::from("end_time must not be in the future"));
+        }
+        if end - start > Duration::minutes(config.max_window_minutes as i64) {
+            return Err(format!(
+                "window is longer than the maximum of {} minutes",
+                config.max_window_minutes
+            ));
+        }
+        Ok(Self {
+            since: start_point.to_nrql(start),
+            until: end_point.to_nrql(end),
+            start,
+            end,
+        })
+    }
+}
+
+/// Resolves a single time, in any format `TimeWindow::parse` accepts, against the server clock.
+pub fn parse_time(value: &str, config: &TimeConfig) -> Result<DateTime<Utc>, String> {
+    TimePoint::parse(value, config.get_timezone())?.resolve(Utc::now())
+}
+
+/// Renders a `compare_with` value like `1 week ago` as a `COMPARE WITH` clause.
+pub fn compare_with(value: &str) -> Result<String, String> {
+    match nrql::parse_relative(value) {
+        Some(duration) if duration.as_secs() > 0 => {
+            Ok(format!(" COMPARE WITH {} seconds ago", duration.as_secs()))
+        }
+        _ => Err(format!(
+            "compare_with: unsupported value '{}', use relative time like '1 week ago'",
+            value
+        )),
+    }
+}
+
+/// Reads a time field sent either as a string or as a JSON number (epoch milliseconds).
+pub fn string_or_number<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(u64),
+    }
+    match StringOrNumber::deserialize(deserializer) {
+        Ok(StringOrNumber::String(s)) => Ok(s),
+        Ok(StringOrNumber::Number(n)) => Ok(n.to_string()),
+        Err(_) => Err(de::Error::custom("expected a string or epoch milliseconds")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(timezone: &str) -> TimeConfig {
+        TimeConfig {
+            timezone: timezone.to_string(),
+            max_window_minutes: 60,
+        }
+    }
+
+    #[test]
+    fn relative_times_stay_relative() {
+        let window = TimeWindow::parse("5 minutes ago", "now", &config("UTC")).unwrap();
+        assert_eq!(window.since, "300 seconds ago");
+        assert_eq!(window.until, "now");
+        assert_eq!((window.end - window.start).num_seconds(), 300);
+    }
+
+    #[test]
+    fn absolute_times_become_epoch_millis() {
+        let start = Utc::now() - Duration::minutes(30);
+        let end = start + Duration::minutes(10);
+        let window =
+            TimeWindow::parse(&start.to_rfc3339(), &end.to_rfc3339(), &config("UTC")).unwrap();
+        assert_eq!(window.since, start.timestamp_millis().to_string());
+        assert_eq!(window.until, end.timestamp_millis().to_string());
+
+        let millis = start.timestamp_millis().to_string();
+        let window = TimeWindow::parse(&millis, "now", &config("UTC")).unwrap();
+        assert_eq!(window.since, millis);
+    }
+
+    #[test]
+    fn local_times_use_the_configured_timezone() {
+        let tz: Tz = "Asia/Jakarta".parse().unwrap();
+        let local = (Utc::now() - Duration::minutes(20)).with_timezone(&tz);
+        let value = local.format("%Y-%m-%d %H:%M:%S").to_string();
+        let window = TimeWindow::parse(&value, "now", &config("Asia/Jakarta")).unwrap();
+        assert_eq!(window.start.timestamp(), local.timestamp());
+    }
+
+    #[test]
+    fn start_must_be_before_end() {
+        let err = TimeWindow::parse("now", "5 minutes ago", &config("UTC")).unwrap_err();
+        assert_eq!(err, "start_time must be before end_time");
+        assert!(TimeWindow::parse("now", "now", &config("UTC")).is_err());
+    }
+
+    #[test]
+    fn end_may_not_be_in_the_future() {
+        let future = (Utc::now() + Duration::minutes(5)).to_rfc3339();
+        let err = TimeWindow::parse("5 minutes ago", &future, &config("UTC")).unwrap_err();
+        assert_eq!(err, "end_time must not be in the future");
+
+        // clock skew within the tolerance is accepted
+        let skewed = (Utc::now() + Duration::seconds(30)).to_rfc3339();
+        assert!(TimeWindow::parse("5 minutes ago", &skewed, &config("UTC")).is_ok());
+    }
+
+    #[test]
+    fn window_may_not_exceed_the_maximum() {
+        assert!(TimeWindow::parse("60 minutes ago", "now", &config("UTC")).is_ok());
+        let err = TimeWindow::parse("61 minutes ago", "now", &config("UTC")).unwrap_err();
+        assert_eq!(err, "window is longer than the maximum of 60 minutes");
+    }
+
+    #[test]
+    fn unsupported_times_name_the_field() {
+        let err = TimeWindow::parse("yesterday", "now", &config("UTC")).unwrap_err();
+        assert!(err.starts_with("start_time: unsupported time 'yesterday'"));
+        let err = TimeWindow::parse("1 hour ago", "soon", &config("UTC")).unwrap_err();
+        assert!(err.starts_with("end_time:"));
+    }
+
+    #[test]
+    fn compare_with_renders_seconds() {
+        assert_eq!(
+            compare_with("1 week ago").unwrap(),
+            " COMPARE WITH 604800 seconds ago"
+        );
+        assert!(compare_with("0 minutes ago").is_err());
+        assert!(compare_with("last week").is_err());
+    }
+}
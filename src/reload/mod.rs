@@ -0,0 +1,94 @@
+use {
+    crate::{
+        config::Config,
+        state::{AppState, SharedState},
+    },
+    log::{error, info, warn},
+    std::{
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    },
+    tokio::{
+        signal::unix::{signal, SignalKind},
+        sync::watch,
+    },
+};
+
+/// Watches `enma.yaml` for changes (polling its mtime every `reload.refresh_rate`
+/// seconds) and listens for SIGHUP, swapping in the new state when it validates.
+/// A reloaded `refresh_rate` takes effect right away, including turning polling
+/// on or off. Returns once `stop` changes.
+pub async fn watch(path: String, state: SharedState, mut stop: watch::Receiver<bool>) {
+    let path = PathBuf::from(path);
+    let mut refresh_rate = state.load().config.reload.refresh_rate;
+    let mut last_modified = modified(&path);
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Could not listen for SIGHUP, config reload disabled: {}", e);
+            return;
+        }
+    };
+    let mut ticker = tokio::time::interval(Duration::from_secs(refresh_rate.max(1)));
+    loop {
+        tokio::select! {
+            _ = stop.changed() => {
+                info!("Stopping config watcher");
+                return;
+            }
+            _ = hangup.recv() => {
+                info!("Received SIGHUP, reloading {}", path.display());
+                last_modified = modified(&path);
+            }
+            _ = ticker.tick() => {
+                if refresh_rate == 0 {
+                    continue;
+                }
+                let current = modified(&path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                info!("Detected change in {}, reloading", path.display());
+            }
+        }
+        reload(&path, &state);
+        let reloaded_rate = state.load().config.reload.refresh_rate;
+        if reloaded_rate != refresh_rate {
+            info!(
+                "reload.refresh_rate changed from {}s to {}s",
+                refresh_rate, reloaded_rate
+            );
+            refresh_rate = reloaded_rate;
+            ticker = tokio::time::interval(Duration::from_secs(refresh_rate.max(1)));
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn reload(path: &Path, state: &SharedState) {
+    let config = match Config::new(&path.to_string_lossy()) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Keeping the current config, reload failed: {}", e);
+            return;
+        }
+    };
+    let current = state.load();
+    if current.config.server.host != config.server.host
+        || current.config.server.port != config.server.port
+    {
+        warn!(
+            "server bind address changed from {}:{} to {}:{}, restart enma to apply it",
+            current.config.server.host,
+            current.config.server.port,
+            config.server.host,
+            config.server.port
+        );
+    }
+    state.store(AppState::new(config));
+    info!("Config {} reloaded", path.display());
+}
@@ -0,0 +1,350 @@
+use {
+    crate::{
+        config::{JobConfig, SchedulerConfig},
+        state::{AppState, MetricValue, SharedState},
+        telemetry::metrics::WEBHOOK_DELIVERIES,
+    },
+    chrono::Utc,
+    hmac::{Hmac, Mac},
+    log::{error, info, warn},
+    serde::Serialize,
+    serde_json::json,
+    sha2::Sha256,
+    std::{
+        collections::HashMap,
+        io::Write,
+        time::{Duration, Instant},
+    },
+    tokio::{sync::watch, task::JoinHandle},
+};
+
+pub const SIGNATURE_HEADER: &str = "x-enma-signature";
+
+/// What a webhook receives for every run of a job.
+#[derive(Serialize, Debug)]
+pub struct Payload {
+    pub job: String,
+    pub metric: String,
+    pub application_name: String,
+    /// Null when New Relic had no data for the window
+    pub value: Option<f32>,
+    pub nrql: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub timestamp: String,
+}
+
+/// Runs the configured jobs whenever they are due and pushes each result to
+/// their webhooks. Jobs are re-read from the current config every second, so
+/// reloads add, change or drop jobs without a restart. A job whose previous run
+/// is still delivering skips its turn, so runs never overlap or arrive out of
+/// order. Returns once `stop` changes and the runs in flight have finished.
+pub async fn run(state: SharedState, mut stop: watch::Receiver<bool>) {
+    let mut last_run: HashMap<String, Instant> = HashMap::new();
+    let mut in_flight: HashMap<String, JoinHandle<()>> = HashMap::new();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = stop.changed() => {
+                info!("Stopping scheduler, waiting for {} running jobs", in_flight.len());
+                for (_, run) in in_flight {
+                    let _ = run.await;
+                }
+                return;
+            }
+            _ = ticker.tick() => {}
+        }
+        in_flight.retain(|_, run| !run.is_finished());
+        let current = state.load();
+        let jobs = &current.config.scheduler.jobs;
+        last_run.retain(|name, _| jobs.iter().any(|j| &j.name == name));
+        for job in jobs {
+            let interval = Duration::from_secs(job.interval);
+            if matches!(last_run.get(&job.name), Some(at) if at.elapsed() < interval) {
+                continue;
+            }
+            last_run.insert(job.name.clone(), Instant::now());
+            if in_flight.contains_key(&job.name) {
+                warn!(
+                    "Scheduled job {} is still running its previous run, skipping this one",
+                    job.name
+                );
+                continue;
+            }
+            let state = state.clone();
+            let name = job.name.clone();
+            let run = actix_web::rt::spawn(async move { run_job(&state, &name).await });
+            in_flight.insert(job.name.clone(), run);
+        }
+    }
+}
+
+async fn run_job(state: &SharedState, name: &str) {
+    let current = state.load();
+    let job = match current
+        .config
+        .scheduler
+        .jobs
+        .iter()
+        .find(|j| j.name == name)
+    {
+        Some(job) => job,
+        None => return,
+    };
+    let payload = match query(&current, job).await {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Scheduled job {} failed: {}", job.name, e);
+            return;
+        }
+    };
+    let body = match serde_json::to_vec(&payload) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Could not encode the payload of job {}: {}", job.name, e);
+            return;
+        }
+    };
+    for url in &job.webhooks {
+        deliver(
+            &current.webhook_client,
+            &current.config.scheduler,
+            &job.name,
+            url,
+            &body,
+        )
+        .await;
+    }
+}
+
+async fn query(current: &AppState, job: &JobConfig) -> Result<Payload, String> {
+    let metric = job
+        .get_metric()
+        .ok_or_else(|| format!("unknown metric {}", job.metric))?;
+    let MetricValue {
+        value,
+        nrql,
+        window,
+    } = current
+        .fetch_metric(
+            metric,
+            &job.application_name,
+            &job.since,
+            &job.until,
+            &job.k8s,
+            "scheduler",
+        )
+        .await?;
+    Ok(Payload {
+        job: job.name.clone(),
+        metric: metric.slug().to_string(),
+        application_name: job.application_name.clone(),
+        value,
+        nrql,
+        start_time: window.start.to_rfc3339(),
+        end_time: window.end.to_rfc3339(),
+        timestamp: Utc::now().to_rfc3339(),
+    })
+}
+
+/// `sha256=<hex HMAC-SHA256 of body>`, so receivers can check the payload came from enma.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// POSTs `body` to `url` signed with `scheduler.secret`, retrying with exponential
+/// backoff, and dead-letters it once `max_attempts` are used up. `name` labels the
+/// delivery in logs and metrics.
+pub async fn deliver(
+    client: &reqwest::Client,
+    config: &SchedulerConfig,
+    name: &str,
+    url: &str,
+    body: &[u8],
+) {
+    let signature = sign(&config.secret, body);
+    let mut backoff = Duration::from_millis(config.retry_backoff_ms);
+    let mut last_error = String::new();
+    for attempt in 1..=config.max_attempts {
+        let result = client
+            .post(url)
+            .timeout(Duration::from_secs(config.timeout))
+            .header("content-type", "application/json")
+            .header(SIGNATURE_HEADER, signature.as_str())
+            .body(body.to_vec())
+            .send()
+            .await;
+        match result {
+            Ok(resp) if resp.status().is_success() => {
+                WEBHOOK_DELIVERIES
+                    .with_label_values(&[name, "delivered"])
+                    .inc();
+                info!("Delivered {} to {}", name, url);
+                return;
+            }
+            Ok(resp) => last_error = format!("webhook responded with {}", resp.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+        if attempt < config.max_attempts {
+            warn!(
+                "Delivering {} to {} failed (attempt {}/{}): {}, retrying in {:?}",
+                name, url, attempt, config.max_attempts, last_error, backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    WEBHOOK_DELIVERIES
+        .with_label_values(&[name, "dead_lettered"])
+        .inc();
+    dead_letter(config, name, url, body, &last_error);
+}
+
+fn dead_letter(config: &SchedulerConfig, name: &str, url: &str, body: &[u8], reason: &str) {
+    let entry = json!({
+        "job": name,
+        "url": url,
+        "attempts": config.max_attempts,
+        "error": reason,
+        "failed_at": Utc::now().to_rfc3339(),
+        "payload": serde_json::from_slice::<serde_json::Value>(body).unwrap_or_default(),
+    });
+    error!("Dead-lettering {} for {}: {}", name, url, entry);
+    if let Some(path) = &config.dead_letter_path {
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut f| writeln!(f, "{}", entry));
+        if let Err(e) = written {
+            error!("Could not write to dead-letter file {}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::sync::{Arc, Mutex},
+        tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        },
+    };
+
+    /// Local webhook answering each request with the next of `statuses` (the
+    /// last one repeats), recording the signature header of every request.
+    async fn webhook(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let signatures = Arc::new(Mutex::new(Vec::new()));
+        let received = signatures.clone();
+        tokio::spawn(async move {
+            for i in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request_complete(&request) {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let signature = request
+                    .lines()
+                    .find_map(|l| l.strip_prefix("x-enma-signature: "))
+                    .unwrap_or_default()
+                    .to_string();
+                received.lock().unwrap().push(signature);
+                let status = statuses[i.min(statuses.len() - 1)];
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, signatures)
+    }
+
+    /// Whether `request` holds the headers and the whole body.
+    fn request_complete(request: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(request);
+        let (head, body) = match text.split_once("\r\n\r\n") {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let length = head
+            .lines()
+            .find_map(|l| {
+                l.to_lowercase()
+                    .strip_prefix("content-length: ")?
+                    .parse()
+                    .ok()
+            })
+            .unwrap_or(0);
+        body.len() >= length
+    }
+
+    fn config(dead_letter_path: Option<String>) -> SchedulerConfig {
+        SchedulerConfig {
+            secret: String::from("Jefe"),
+            max_attempts: 3,
+            retry_backoff_ms: 1,
+            timeout: 5,
+            dead_letter_path,
+            ..SchedulerConfig::default()
+        }
+    }
+
+    #[test]
+    fn sign_matches_a_known_hmac() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn delivery_is_retried_until_it_succeeds() {
+        let (url, signatures) = webhook(vec![500, 503, 200]).await;
+        let body = br#"{"job":"p95"}"#;
+        deliver(&reqwest::Client::new(), &config(None), "p95", &url, body).await;
+        let signatures = signatures.lock().unwrap();
+        assert_eq!(signatures.len(), 3);
+        assert!(signatures.iter().all(|s| *s == sign("Jefe", body)));
+    }
+
+    #[tokio::test]
+    async fn exhausted_delivery_is_dead_lettered() {
+        let path =
+            std::env::temp_dir().join(format!("enma-dead-letter-{}.jsonl", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = std::fs::remove_file(&path);
+        let (url, signatures) = webhook(vec![500]).await;
+        deliver(
+            &reqwest::Client::new(),
+            &config(Some(path.clone())),
+            "p95",
+            &url,
+            br#"{"job":"p95","value":1.5}"#,
+        )
+        .await;
+        assert_eq!(signatures.lock().unwrap().len(), 3);
+        let written = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let entry: serde_json::Value = serde_json::from_str(written.trim()).unwrap();
+        assert_eq!(entry["job"], "p95");
+        assert_eq!(entry["url"], url.as_str());
+        assert_eq!(entry["attempts"], 3);
+        assert_eq!(entry["payload"]["value"], 1.5);
+        assert!(entry["error"].as_str().unwrap().contains("500"));
+    }
+}
@@ -0,0 +1,66 @@
+use {
+    log::info,
+    std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    tokio::{
+        signal::unix::{signal, SignalKind},
+        sync::watch,
+    },
+};
+
+/// Readiness flag and stop signal shared by the HTTP server, the readiness
+/// probe and the background tasks.
+#[derive(Clone)]
+pub struct Lifecycle {
+    ready: Arc<AtomicBool>,
+    stop_tx: Arc<watch::Sender<bool>>,
+    stop_rx: watch::Receiver<bool>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        let (stop_tx, stop_rx) = watch::channel(false);
+        Self {
+            ready: Arc::new(AtomicBool::new(true)),
+            stop_tx: Arc::new(stop_tx),
+            stop_rx,
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn mark_not_ready(&self) {
+        self.ready.store(false, Ordering::SeqCst)
+    }
+
+    /// Receiver that changes once background tasks should stop.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.stop_rx.clone()
+    }
+
+    pub fn stop_background_tasks(&self) {
+        let _ = self.stop_tx.send(true);
+    }
+}
+
+/// Resolves with the signal name once SIGTERM or SIGINT arrives.
+pub async fn wait_for_signal() -> std::io::Result<&'static str> {
+    let mut term = signal(SignalKind::terminate())?;
+    let mut int = signal(SignalKind::interrupt())?;
+    let name = tokio::select! {
+        _ = term.recv() => "SIGTERM",
+        _ = int.recv() => "SIGINT",
+    };
+    info!("Received {}, starting graceful shutdown", name);
+    Ok(name)
+}
@@ -0,0 +1,368 @@
+use {
+    crate::{
+        config::SloConfig, newrelic::newrelic::Newrelic, telemetry::metrics::record_cache_lookup,
+    },
+    serde::Serialize,
+    serde_json::Value,
+    std::{
+        collections::HashMap,
+        fmt,
+        sync::{Arc, Mutex, PoisonError},
+        time::{Duration, Instant},
+    },
+    tokio::sync::Mutex as AsyncMutex,
+};
+
+/// Short windows burn rates are reported for, as (label, seconds).
+const BURN_WINDOWS: [(&str, u64); 3] = [("1h", 3600), ("6h", 6 * 3600), ("3d", 3 * 86400)];
+
+#[derive(Debug)]
+pub enum SloError {
+    /// New Relic could not be reached or answered with something unreadable
+    Upstream(String),
+    /// New Relic rejected the query or it returned no count
+    Query(String),
+}
+
+impl fmt::Display for SloError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upstream(e) | Self::Query(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Good and total event counts over one window.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Counts {
+    pub good: f64,
+    pub total: f64,
+}
+
+impl Counts {
+    /// Fraction of bad events, None without events.
+    fn error_ratio(&self) -> Option<f64> {
+        if self.total <= 0.0 {
+            return None;
+        }
+        Some(((self.total - self.good) / self.total).max(0.0))
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorBudget {
+    /// Percentage of events allowed to be bad
+    pub allowed_percent: f64,
+    pub consumed_percent: Option<f64>,
+    /// Negative once the budget is overspent
+    pub remaining_percent: Option<f64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BurnRate {
+    pub window: &'static str,
+    /// How many times faster than sustainable the budget is being spent
+    pub burn_rate: Option<f64>,
+    #[serde(flatten)]
+    pub counts: Counts,
+}
+
+/// A multi-window burn alert: fires when both windows burn faster than `threshold`.
+#[derive(Serialize, Debug)]
+pub struct BurnAlert {
+    pub name: &'static str,
+    pub windows: [&'static str; 2],
+    pub threshold: f64,
+    pub burning: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub slo: String,
+    pub objective: f64,
+    pub window_days: u64,
+    /// Percentage of good events over the whole window
+    pub sli: Option<f64>,
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub error_budget: ErrorBudget,
+    pub burn_rates: Vec<BurnRate>,
+    pub alerts: Vec<BurnAlert>,
+}
+
+type CachedCounts = Arc<AsyncMutex<Option<(Counts, Instant)>>>;
+
+/// Counts per SLO and window, reused for `cache_seconds` so polling
+/// `GET /slo/{name}` doesn't run eight New Relic queries each time. Requests
+/// missing the same entry wait for one fetch instead of each running it. It
+/// lives in `AppState`, so a reload starts it empty.
+#[derive(Default)]
+pub struct SloCache(Mutex<HashMap<(String, u64), CachedCounts>>);
+
+impl SloCache {
+    fn entry(&self, slo: &str, seconds: u64) -> CachedCounts {
+        let mut entries = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        entries
+            .entry((slo.to_string(), seconds))
+            .or_default()
+            .clone()
+    }
+}
+
+/// Queries the SLO's window and the 1h, 6h and 3d burn windows and works out
+/// the SLI, error budget and multi-window burn alerts from the SRE workbook.
+pub async fn report(
+    newrelic: &Newrelic,
+    cache: &SloCache,
+    slo: &SloConfig,
+) -> Result<Report, SloError> {
+    let (full, hour, six_hours, three_days) = tokio::try_join!(
+        counts(newrelic, cache, slo, slo.window_days * 86400),
+        counts(newrelic, cache, slo, BURN_WINDOWS[0].1),
+        counts(newrelic, cache, slo, BURN_WINDOWS[1].1),
+        counts(newrelic, cache, slo, BURN_WINDOWS[2].1),
+    )?;
+    Ok(summarize(slo, full, [hour, six_hours, three_days]))
+}
+
+/// Works out the report from the counts over the SLO window and each of `BURN_WINDOWS`.
+fn summarize(slo: &SloConfig, full: Counts, windows: [Counts; 3]) -> Report {
+    let budget = slo.get_error_budget();
+    let burn_rates: Vec<BurnRate> = BURN_WINDOWS
+        .iter()
+        .zip(windows)
+        .map(|((window, _), counts)| BurnRate {
+            window,
+            burn_rate: counts.error_ratio().map(|ratio| round(ratio / budget)),
+            counts,
+        })
+        .collect();
+    let burning = |windows: [usize; 2], threshold: f64| {
+        windows
+            .iter()
+            .all(|&i| matches!(burn_rates[i].burn_rate, Some(rate) if rate > threshold))
+    };
+    let alerts = vec![
+        BurnAlert {
+            name: "fast_burn",
+            windows: [BURN_WINDOWS[0].0, BURN_WINDOWS[1].0],
+            threshold: slo.fast_burn_threshold,
+            burning: burning([0, 1], slo.fast_burn_threshold),
+        },
+        BurnAlert {
+            name: "slow_burn",
+            windows: [BURN_WINDOWS[1].0, BURN_WINDOWS[2].0],
+            threshold: slo.slow_burn_threshold,
+            burning: burning([1, 2], slo.slow_burn_threshold),
+        },
+    ];
+    let consumed = full.error_ratio().map(|ratio| ratio / budget * 100.0);
+    Report {
+        slo: slo.name.clone(),
+        objective: slo.objective,
+        window_days: slo.window_days,
+        sli: full.error_ratio().map(|ratio| round((1.0 - ratio) * 100.0)),
+        counts: full,
+        error_budget: ErrorBudget {
+            allowed_percent: round(budget * 100.0),
+            consumed_percent: consumed.map(round),
+            remaining_percent: consumed.map(|c| round(100.0 - c)),
+        },
+        burn_rates,
+        alerts,
+    }
+}
+
+/// Drops float noise like 0.09999999999998899 from the reported figures.
+fn round(value: f64) -> f64 {
+    (value * 1e6).round() / 1e6
+}
+
+/// Good and total counts over the last `seconds`, from the cache while fresh.
+async fn counts(
+    newrelic: &Newrelic,
+    cache: &SloCache,
+    slo: &SloConfig,
+    seconds: u64,
+) -> Result<Counts, SloError> {
+    let entry = cache.entry(&slo.name, seconds);
+    let mut cached = entry.lock().await;
+    if let Some((counts, at)) = *cached {
+        if at.elapsed() < Duration::from_secs(slo.cache_seconds) {
+            record_cache_lookup("slo", true);
+            return Ok(counts);
+        }
+    }
+    record_cache_lookup("slo", false);
+    let (good, total) = tokio::try_join!(
+        count(newrelic, &slo.good, seconds),
+        count(newrelic, &slo.total, seconds),
+    )?;
+    let counts = Counts { good, total };
+    *cached = Some((counts, Instant::now()));
+    Ok(counts)
+}
+
+/// Runs `query` over the last `seconds` and reads its single numeric result.
+async fn count(newrelic: &Newrelic, query: &str, seconds: u64) -> Result<f64, SloError> {
+    let nrql = format!("{} SINCE {} seconds ago UNTIL now", query.trim(), seconds);
+    let raw = newrelic
+        .run_nrql(&nrql, "slo")
+        .await
+        .map_err(|e| SloError::Upstream(e.to_string()))?;
+    if let Some(e) = raw.get("error") {
+        let message = e
+            .as_str()
+            .map(String::from)
+            .unwrap_or_else(|| e.to_string());
+        return Err(SloError::Query(message));
+    }
+    single_value(&raw, &nrql)
+}
+
+/// The only number across the query's results. A query selecting several
+/// aggregates has no single count, so it is an error rather than a guess.
+fn single_value(raw: &Value, nrql: &str) -> Result<f64, SloError> {
+    let values: Vec<f64> = raw
+        .get("results")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object)
+        .flat_map(|result| result.values().filter_map(Value::as_f64))
+        .collect();
+    match values.as_slice() {
+        [value] => Ok(*value),
+        [] => Err(SloError::Query(format!("{} returned no count", nrql))),
+        _ => Err(SloError::Query(format!(
+            "{} returned {} numbers, select exactly one aggregate",
+            nrql,
+            values.len()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slo() -> SloConfig {
+        SloConfig {
+            name: String::from("checkout-availability"),
+            good: String::from("SELECT count(*) FROM Transaction WHERE error IS false"),
+            total: String::from("SELECT count(*) FROM Transaction"),
+            objective: 99.9,
+            window_days: 30,
+            fast_burn_threshold: 14.4,
+            slow_burn_threshold: 1.0,
+            cache_seconds: 60,
+        }
+    }
+
+    fn counts(good: f64, total: f64) -> Counts {
+        Counts { good, total }
+    }
+
+    #[test]
+    fn sli_and_error_budget() {
+        let healthy = counts(1000.0, 1000.0);
+        let report = summarize(&slo(), counts(999_500.0, 1_000_000.0), [healthy; 3]);
+        assert_eq!(report.sli, Some(99.95));
+        assert_eq!(report.error_budget.allowed_percent, 0.1);
+        assert_eq!(report.error_budget.consumed_percent, Some(50.0));
+        assert_eq!(report.error_budget.remaining_percent, Some(50.0));
+    }
+
+    #[test]
+    fn overspent_budget_goes_negative() {
+        let healthy = counts(1000.0, 1000.0);
+        let report = summarize(&slo(), counts(997_000.0, 1_000_000.0), [healthy; 3]);
+        assert_eq!(report.error_budget.consumed_percent, Some(300.0));
+        assert_eq!(report.error_budget.remaining_percent, Some(-200.0));
+    }
+
+    #[test]
+    fn burn_rate_is_error_ratio_over_budget() {
+        let report = summarize(
+            &slo(),
+            counts(1000.0, 1000.0),
+            [
+                counts(9_800.0, 10_000.0),
+                counts(59_400.0, 60_000.0),
+                counts(719_280.0, 720_000.0),
+            ],
+        );
+        let rates: Vec<Option<f64>> = report.burn_rates.iter().map(|b| b.burn_rate).collect();
+        assert_eq!(rates, vec![Some(20.0), Some(10.0), Some(1.0)]);
+        assert_eq!(report.burn_rates[0].window, "1h");
+    }
+
+    #[test]
+    fn alerts_need_both_windows_above_the_threshold() {
+        // 1h at 20 but 6h at 10: no page, 6h at 10 and 3d at 1.5: ticket
+        let report = summarize(
+            &slo(),
+            counts(1000.0, 1000.0),
+            [
+                counts(9_800.0, 10_000.0),
+                counts(59_400.0, 60_000.0),
+                counts(718_920.0, 720_000.0),
+            ],
+        );
+        assert_eq!(report.alerts[0].name, "fast_burn");
+        assert!(!report.alerts[0].burning);
+        assert_eq!(report.alerts[1].name, "slow_burn");
+        assert!(report.alerts[1].burning);
+
+        // exactly at the threshold is not above it
+        let report = summarize(
+            &slo(),
+            counts(1000.0, 1000.0),
+            [
+                counts(1000.0, 1000.0),
+                counts(59_400.0, 60_000.0),
+                counts(719_280.0, 720_000.0),
+            ],
+        );
+        assert!(!report.alerts[1].burning);
+    }
+
+    #[test]
+    fn count_needs_exactly_one_number() {
+        let nrql = "SELECT count(*) FROM Transaction";
+        let raw = serde_json::json!({ "results": [{ "count": 42 }] });
+        assert_eq!(single_value(&raw, nrql).unwrap(), 42.0);
+        let raw = serde_json::json!({ "results": [{ "count": 42, "name": "checkout" }] });
+        assert_eq!(single_value(&raw, nrql).unwrap(), 42.0);
+        let raw = serde_json::json!({ "results": [{ "count": 42 }, { "sum": 7 }] });
+        assert!(
+            matches!(single_value(&raw, nrql), Err(SloError::Query(e)) if e.ends_with("returned 2 numbers, select exactly one aggregate"))
+        );
+        let raw = serde_json::json!({ "results": [{ "count": 42, "average": 1.5 }] });
+        assert!(single_value(&raw, nrql).is_err());
+        let raw = serde_json::json!({ "results": [] });
+        assert!(
+            matches!(single_value(&raw, nrql), Err(SloError::Query(e)) if e.ends_with("returned no count"))
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_entries_are_per_slo_and_window() {
+        let cache = SloCache::default();
+        let entry = cache.entry("checkout", 3600);
+        *entry.lock().await = Some((counts(1.0, 2.0), Instant::now()));
+        assert!(cache.entry("checkout", 3600).lock().await.is_some());
+        assert!(cache.entry("checkout", 21600).lock().await.is_none());
+        assert!(cache.entry("cart", 3600).lock().await.is_none());
+    }
+
+    #[test]
+    fn no_events_means_no_figures() {
+        let empty = counts(0.0, 0.0);
+        let report = summarize(&slo(), empty, [empty; 3]);
+        assert_eq!(report.sli, None);
+        assert_eq!(report.error_budget.consumed_percent, None);
+        assert!(report.burn_rates.iter().all(|b| b.burn_rate.is_none()));
+        assert!(report.alerts.iter().all(|a| !a.burning));
+    }
+}
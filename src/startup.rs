@@ -1,42 +1,149 @@
 use {
     crate::{
+        alerts::AlertStore,
         config::Config,
+        handler::alerts::alerts,
+        handler::health::{live, ready},
+        handler::history::history,
+        handler::slo::slo,
         handler::v1::{
-            cpu_requested_core::cpu_requested_core, cpu_used_core::cpu_used_core,
-            memory_heap_used::memory_heap_used, response_time_average::response_time_average,
-            thread_count::thread_count, throughput::throughput, total_pods::total_pods,
+            apdex::apdex,
+            container_restarts::container_restarts,
+            cpu_limit_core::cpu_limit_core,
+            cpu_requested_core::cpu_requested_core,
+            cpu_throttling_ratio::cpu_throttling_ratio,
+            cpu_used_core::cpu_used_core,
+            cpu_utilization_limit::cpu_utilization_limit,
+            cpu_utilization_request::cpu_utilization_request,
+            error_rate::error_rate,
+            gc_frequency::gc_frequency,
+            gc_pause_time::gc_pause_time,
+            loaded_class_count::loaded_class_count,
+            memory_heap_committed::memory_heap_committed,
+            memory_heap_max::memory_heap_max,
+            memory_heap_used::memory_heap_used,
+            memory_heap_utilization::memory_heap_utilization,
+            memory_limit::memory_limit,
+            memory_metaspace_used::memory_metaspace_used,
+            memory_requested::memory_requested,
+            memory_utilization_limit::memory_utilization_limit,
+            memory_utilization_request::memory_utilization_request,
+            memory_working_set::memory_working_set,
+            nrql::run_nrql,
+            oom_killed::oom_killed,
+            response_time_average::response_time_average,
+            response_time_percentile::{
+                response_time_p50, response_time_p90, response_time_p95, response_time_p99,
+                response_time_percentile,
+            },
+            thread_count::thread_count,
+            throughput::throughput,
+            total_pods::total_pods,
+        },
+        log::context::{self, LogContext, REQUEST_ID_HEADER},
+        reload, scheduler, shutdown,
+        shutdown::Lifecycle,
+        state::{AppState, SharedState},
+        telemetry::{
+            metrics::{metrics, record_request},
+            trace::request_span,
         },
-        newrelic::newrelic::Newrelic,
     },
     actix_web::{
-        dev::Server,
-        error, middleware,
+        dev::{Server, Service},
+        error,
+        http::{HeaderName, HeaderValue},
+        middleware,
         web::{scope, Data, JsonConfig},
         App, HttpResponse, HttpServer,
     },
-    std::net::TcpListener,
+    log::info,
+    std::{
+        net::TcpListener,
+        time::{Duration, Instant},
+    },
+    tokio::task::JoinHandle,
+    tracing::Instrument,
 };
 
 pub struct Application {
     server: Server,
+    lifecycle: Lifecycle,
+    shutdown_delay: u64,
+    background_tasks: Vec<JoinHandle<()>>,
 }
 
 impl Application {
-    pub async fn build(config: Config) -> Result<Self, std::io::Error> {
+    pub async fn build(config: Config, config_path: &str) -> Result<Self, std::io::Error> {
         let address = format!("{}:{}", config.server.host, config.server.port);
         let listener = TcpListener::bind(&address)?;
-        let newrelic = Newrelic::new(&config.newrelic);
+        let shutdown_delay = config.server.shutdown_delay;
+        let shutdown_timeout = config.server.shutdown_timeout;
+        let lifecycle = Lifecycle::new();
+        let state = SharedState::new(AppState::new(config));
+        let alert_store = AlertStore::default();
+        let background_tasks = vec![
+            actix_web::rt::spawn(reload::watch(
+                config_path.to_string(),
+                state.clone(),
+                lifecycle.subscribe(),
+            )),
+            actix_web::rt::spawn(scheduler::run(state.clone(), lifecycle.subscribe())),
+            actix_web::rt::spawn(crate::alerts::run(
+                state.clone(),
+                alert_store.clone(),
+                lifecycle.subscribe(),
+            )),
+        ];
 
-        let server = run(listener, newrelic)?;
-        Ok(Self { server: server })
+        let server = run(
+            listener,
+            state,
+            alert_store,
+            lifecycle.clone(),
+            shutdown_timeout,
+        )?;
+        Ok(Self {
+            server,
+            lifecycle,
+            shutdown_delay,
+            background_tasks,
+        })
     }
 
+    /// Serves until SIGTERM/SIGINT, then fails readiness, waits `shutdown_delay`
+    /// for load balancers to notice, drains in-flight requests and stops background tasks.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        let server = self.server.clone();
+        tokio::select! {
+            res = self.server => return res,
+            res = shutdown::wait_for_signal() => { res?; }
+        }
+        self.lifecycle.mark_not_ready();
+        info!(
+            "Readiness now failing, waiting {}s before stopping the HTTP server",
+            self.shutdown_delay
+        );
+        tokio::time::sleep(Duration::from_secs(self.shutdown_delay)).await;
+        info!("Stopping the HTTP server, draining in-flight requests");
+        server.stop(true).await;
+        info!("HTTP server stopped, stopping background tasks");
+        self.lifecycle.stop_background_tasks();
+        for task in self.background_tasks {
+            let _ = task.await;
+        }
+        info!("Shutdown complete");
+        Ok(())
     }
 }
 
-fn run(listener: TcpListener, newrelic: Newrelic) -> Result<Server, std::io::Error> {
+fn run(
+    listener: TcpListener,
+    state: SharedState,
+    alert_store: AlertStore,
+    lifecycle: Lifecycle,
+    shutdown_timeout: u64,
+) -> Result<Server, std::io::Error> {
     let server = HttpServer::new(move || {
         App::new()
             .app_data(JsonConfig::default().error_handler(|err, _req| {
@@ -49,19 +156,94 @@ fn run(listener: TcpListener, newrelic: Newrelic) -> Result<Server, std::io::Err
                 .into()
             }))
             .wrap(middleware::Compress::default())
-            .wrap(middleware::Logger::default())
+            .wrap(middleware::Logger::new(
+                r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T request_id=%{X-Request-Id}i"#,
+            ))
+            .wrap_fn(|req, srv| {
+                let started = Instant::now();
+                let method = req.method().to_string();
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await?;
+                    record_request(&res, &method, started);
+                    Ok(res)
+                }
+            })
+            .wrap_fn(|req, srv| {
+                let span = request_span(&req);
+                let fut = span.in_scope(|| srv.call(req));
+                async move {
+                    let res = fut.await?;
+                    tracing::Span::current().record("http.status_code", res.status().as_u16());
+                    Ok(res)
+                }
+                .instrument(span)
+            })
+            .wrap_fn(|mut req, srv| {
+                let request_id = context::request_id(&mut req);
+                let log_context = LogContext {
+                    request_id: request_id.clone(),
+                    ..LogContext::default()
+                };
+                let fut = srv.call(req);
+                context::scope(log_context, async move {
+                    let mut res = fut.await?;
+                    if let Ok(value) = HeaderValue::from_str(&request_id) {
+                        res.headers_mut()
+                            .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                    }
+                    Ok(res)
+                })
+            })
+            .service(metrics)
+            .app_data(Data::new(lifecycle.clone()))
+            .service(live)
+            .service(ready)
+            .app_data(Data::new(alert_store.clone()))
+            .service(alerts)
+            .app_data(Data::new(state.clone()))
+            .service(slo)
+            .service(history)
             .service(
                 scope("/newrelic/v1")
-                    .app_data(Data::new(newrelic.clone()))
+                    .app_data(Data::new(state.clone()))
                     .service(cpu_requested_core)
                     .service(cpu_used_core)
+                    .service(apdex)
+                    .service(error_rate)
                     .service(memory_heap_used)
+                    .service(gc_pause_time)
+                    .service(gc_frequency)
+                    .service(memory_heap_committed)
+                    .service(memory_heap_max)
+                    .service(memory_metaspace_used)
+                    .service(loaded_class_count)
+                    .service(memory_heap_utilization)
+                    .service(run_nrql)
                     .service(response_time_average)
+                    .service(response_time_p50)
+                    .service(response_time_p90)
+                    .service(response_time_p95)
+                    .service(response_time_p99)
+                    .service(response_time_percentile)
                     .service(thread_count)
                     .service(throughput)
-                    .service(total_pods),
+                    .service(total_pods)
+                    .service(memory_working_set)
+                    .service(memory_requested)
+                    .service(memory_limit)
+                    .service(memory_utilization_request)
+                    .service(memory_utilization_limit)
+                    .service(cpu_limit_core)
+                    .service(cpu_throttling_ratio)
+                    .service(cpu_utilization_request)
+                    .service(cpu_utilization_limit)
+                    .service(container_restarts)
+                    .service(oom_killed),
             )
     })
+    .disable_signals()
+    .shutdown_timeout(shutdown_timeout)
     .listen(listener)?
     .run();
     Ok(server)
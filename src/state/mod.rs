@@ -0,0 +1,141 @@
+use {
+    crate::{
+        config::Config,
+        history::{History, Record},
+        newrelic::{
+            filter::K8sFilter, metric::Metric, model::NewrelicQueryResult, newrelic::Newrelic,
+            window::TimeWindow,
+        },
+        slo::SloCache,
+        telemetry::metrics::record_cache_lookup,
+    },
+    arc_swap::ArcSwap,
+    std::{
+        collections::HashMap,
+        sync::{Arc, RwLock},
+        time::{Duration, Instant},
+    },
+};
+
+/// Everything a handler needs that is derived from `enma.yaml`.
+pub struct AppState {
+    pub config: Config,
+    pub newrelic: Newrelic,
+    pub history: History,
+    pub slo_cache: SloCache,
+    /// Shared by scheduled jobs and alert rules so webhook connections are reused
+    pub webhook_client: reqwest::Client,
+}
+
+/// A metric value fetched outside of an HTTP request.
+pub struct MetricValue {
+    /// None when New Relic had no data for the window
+    pub value: Option<f32>,
+    pub nrql: String,
+    pub window: TimeWindow,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        let newrelic = Newrelic::new(&config.newrelic);
+        let history = History::new(&config.history);
+        Self {
+            config,
+            newrelic,
+            history,
+            slo_cache: SloCache::default(),
+            webhook_client: reqwest::Client::new(),
+        }
+    }
+
+    /// Runs `metric` the way the v1 handlers do, for the scheduler and alert rules.
+    /// `source` names the caller in the history store.
+    pub async fn fetch_metric(
+        &self,
+        metric: Metric,
+        application_name: &str,
+        since: &str,
+        until: &str,
+        k8s: &K8sFilter,
+        source: &str,
+    ) -> Result<MetricValue, String> {
+        let window = TimeWindow::parse(since, until, &self.config.time)?;
+        let target = self.config.resolve_target(&metric, application_name, k8s);
+        let nrql = metric.get_query(&target, &window.since, &window.until);
+        let value = match self.newrelic.go_query(metric, &nrql).await {
+            Ok(NewrelicQueryResult::Ok(res)) => metric.extract(&res),
+            Ok(NewrelicQueryResult::Compare(res)) => metric.extract(res.get_current()),
+            Ok(NewrelicQueryResult::Err(e)) => return Err(e.get_error_msg().to_string()),
+            Err(e) => return Err(e.to_string()),
+        };
+        if let Some(value) = value {
+            self.history
+                .record(Record::new(
+                    application_name,
+                    metric.slug(),
+                    window.start,
+                    window.end,
+                    value,
+                    source,
+                ))
+                .await;
+        }
+        Ok(MetricValue {
+            value,
+            nrql,
+            window,
+        })
+    }
+}
+
+/// Last successful value per app, metric and filter, served while New Relic is down.
+#[derive(Clone, Default)]
+pub struct LastGood(Arc<RwLock<HashMap<String, (f32, Instant)>>>);
+
+impl LastGood {
+    pub fn remember(&self, key: String, value: f32) {
+        if let Ok(mut values) = self.0.write() {
+            values.insert(key, (value, Instant::now()));
+        }
+    }
+
+    /// The value for `key` and its age, unless it is older than `max_age`.
+    pub fn get(&self, key: &str, max_age: Duration) -> Option<(f32, Duration)> {
+        let found = self.0.read().ok().and_then(|values| {
+            let (value, at) = values.get(key)?;
+            Some((*value, at.elapsed())).filter(|(_, age)| *age <= max_age)
+        });
+        record_cache_lookup("last_good", found.is_some());
+        found
+    }
+}
+
+/// Handle to the current `AppState`. A reload swaps the whole state at once;
+/// requests that already called `load` keep the snapshot they started with.
+/// Last good values live outside `AppState` so they survive reloads.
+#[derive(Clone)]
+pub struct SharedState {
+    current: Arc<ArcSwap<AppState>>,
+    last_good: LastGood,
+}
+
+impl SharedState {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(state)),
+            last_good: LastGood::default(),
+        }
+    }
+
+    pub fn load(&self) -> Arc<AppState> {
+        self.current.load_full()
+    }
+
+    pub fn store(&self, state: AppState) {
+        self.current.store(Arc::new(state))
+    }
+
+    pub fn last_good(&self) -> &LastGood {
+        &self.last_good
+    }
+}
@@ -0,0 +1,91 @@
+use {
+    actix_web::{dev::ServiceResponse, get, HttpResponse},
+    lazy_static::lazy_static,
+    prometheus::{
+        register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+        HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+    },
+    std::time::Instant,
+};
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "enma_http_requests_total",
+        "HTTP requests served, by route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "enma_http_request_duration_seconds",
+        "HTTP request latency, by route, method and status",
+        &["route", "method", "status"]
+    )
+    .unwrap();
+    pub static ref UPSTREAM_DURATION: HistogramVec = register_histogram_vec!(
+        "enma_newrelic_query_duration_seconds",
+        "New Relic query latency, by metric",
+        &["metric"]
+    )
+    .unwrap();
+    pub static ref UPSTREAM_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "enma_newrelic_query_errors_total",
+        "Failed New Relic queries, by metric and error class",
+        &["metric", "class"]
+    )
+    .unwrap();
+    pub static ref UPSTREAM_IN_FLIGHT: IntGaugeVec = register_int_gauge_vec!(
+        "enma_newrelic_queries_in_flight",
+        "New Relic queries currently waiting for a response, by metric",
+        &["metric"]
+    )
+    .unwrap();
+    pub static ref WEBHOOK_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "enma_webhook_deliveries_total",
+        "Scheduled results pushed to webhooks, by job and outcome (delivered or dead_lettered)",
+        &["job", "outcome"]
+    )
+    .unwrap();
+    pub static ref STALE_RESPONSES: IntCounterVec = register_int_counter_vec!(
+        "enma_stale_responses_total",
+        "Last good values served because New Relic could not be reached, by metric",
+        &["metric"]
+    )
+    .unwrap();
+    pub static ref CACHE_LOOKUPS: IntCounterVec = register_int_counter_vec!(
+        "enma_cache_lookups_total",
+        "Cache lookups, by cache (last_good or slo) and result (hit or miss)",
+        &["cache", "result"]
+    )
+    .unwrap();
+}
+
+#[get("/internal/metrics")]
+async fn metrics() -> HttpResponse {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        return HttpResponse::InternalServerError().body(e.to_string());
+    }
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(buffer)
+}
+
+/// Counts a lookup in `cache`; the hit ratio is hits over all lookups.
+pub fn record_cache_lookup(cache: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    CACHE_LOOKUPS.with_label_values(&[cache, result]).inc();
+}
+
+/// Records a served request; unmatched paths share one label so scanners can't blow up cardinality.
+pub fn record_request<B>(res: &ServiceResponse<B>, method: &str, started: Instant) {
+    let route = res
+        .request()
+        .match_pattern()
+        .unwrap_or_else(|| String::from("unmatched"));
+    let status = res.status().as_u16().to_string();
+    let labels = [route.as_str(), method, status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(started.elapsed().as_secs_f64());
+}
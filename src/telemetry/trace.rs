@@ -0,0 +1,101 @@
+use {
+    crate::config::TelemetryConfig,
+    actix_web::{dev::ServiceRequest, http::HeaderMap},
+    log::{error, info},
+    opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _},
+    opentelemetry_otlp::{SpanExporter, WithExportConfig},
+    opentelemetry_sdk::{
+        propagation::TraceContextPropagator,
+        trace::{Sampler, SdkTracerProvider},
+        Resource,
+    },
+    tracing::{field, info_span, Span},
+    tracing_opentelemetry::OpenTelemetrySpanExt,
+    tracing_subscriber::layer::SubscriberExt,
+};
+
+/// Installs the global `tracing` subscriber. Spans are only exported when
+/// `telemetry.otlp_endpoint` is set; the returned provider must be shut down
+/// on exit so buffered spans get flushed. A subscriber is installed either way,
+/// otherwise `tracing` falls back to writing every span into the `log` output.
+pub fn init(config: &TelemetryConfig) -> Option<SdkTracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = config
+        .otlp_endpoint
+        .as_ref()
+        .and_then(|endpoint| build_provider(endpoint, config));
+    let layer = provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer("enma")));
+    let subscriber = tracing_subscriber::registry().with(layer);
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        error!("Could not install the tracing subscriber: {}", e);
+        return None;
+    }
+    provider
+}
+
+fn build_provider(endpoint: &str, config: &TelemetryConfig) -> Option<SdkTracerProvider> {
+    let exporter = match SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+    {
+        Ok(e) => e,
+        Err(e) => {
+            error!(
+                "Could not create the OTLP exporter, tracing disabled: {}",
+                e
+            );
+            return None;
+        }
+    };
+    info!("Exporting traces to {}", endpoint);
+    Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                config.sample_ratio,
+            ))))
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.service_name.clone())
+                    .build(),
+            )
+            .build(),
+    )
+}
+
+/// Flushes and stops the exporter.
+pub fn shutdown(provider: SdkTracerProvider) {
+    if let Err(e) = provider.shutdown() {
+        error!("Could not flush pending spans: {}", e);
+    }
+}
+
+/// Reads W3C `traceparent`/`tracestate` from incoming request headers.
+pub struct HeaderExtractor<'a>(pub &'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Root span for an incoming request, continuing the caller's trace when it sent `traceparent`.
+pub fn request_span(req: &ServiceRequest) -> Span {
+    let parent = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(req.headers())));
+    let span = info_span!(
+        "http_request",
+        otel.kind = "server",
+        http.method = %req.method(),
+        http.target = %req.path(),
+        http.status_code = field::Empty,
+    );
+    let _ = span.set_parent(parent);
+    span
+}